@@ -0,0 +1,49 @@
+-- Monsters that charge at the closest enemy they see and never run away
+
+-- Distance between two points of the hex map
+local function distance(a, b)
+    local dx, dy = a.x - b.x, a.y - b.y
+    return math.max(math.abs(dx), math.abs(dy), math.abs(dx + dy))
+end
+
+function act(me)
+    local target = nil
+    for _, cr in ipairs(me.creatures) do
+        if cr.hostile and (target == nil or cr.distance < target.distance) then
+            target = cr
+        end
+    end
+
+    if target == nil then
+        if not me.ahead.passable then
+            return "turn left"
+        elseif me.roll < 0.15 then
+            return "turn right"
+        elseif me.roll < 0.4 then
+            return "wait"
+        end
+        return "move forward"
+    end
+
+    if target.at == "ahead" then
+        return "melee forward"
+    elseif target.at ~= nil then
+        return "melee " .. target.at
+    end
+
+    if not me.ahead.passable then
+        return "turn right"
+    end
+
+    -- Turn towards the target when it's closer on one side
+    local ahead = distance(me.ahead, target)
+    local left = distance(me.left, target)
+    local right = distance(me.right, target)
+    if left < ahead and left <= right then
+        return "turn left"
+    elseif right < ahead then
+        return "turn right"
+    end
+
+    return "run forward"
+end
@@ -1,40 +1,815 @@
 // Copyright 2014 Dawid Ciężarkiewicz
 // See LICENSE file for more information
 
-use creature::Scout;
+use creature::{Race,Human,Scout,Grunt,Heavy};
 use game::Action;
 use game::{Turn,Move,Melee,Run,Wait};
-use hex2d::Direction;
-use hex2d::{Left,Right,Forward};
-use hex2d::Point;
+use hex2d::{Direction,AbsoluteDirection};
+use hex2d::{Left,Right,Forward,Backward};
+use hex2d::{Point,Position};
 use map;
+use noise::Noise;
+use path;
 use std::rand;
 use std::rand::Rng;
+use std::cmp::min;
+use std::iter::range_inclusive;
+use std::mem;
 use std::f32::consts::PI;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::collections::RingBuf;
 
 use creature::CreatureState;
 
+/// Actions queued for an `InputActor`
+pub type ActionQueue = Rc<RefCell<RingBuf<Action>>>;
+
+/// Something deciding what a creature does
+///
+/// Keyboard, scripts and the AI are all actors, and any of them can
+/// control any creature.
 pub trait Actor {
-    fn get_action(&mut self, map : &map::Map, _ : &CreatureState) -> Action;
-    fn proceed_visible(&mut self, map : &map::Map, p : Point);
+    /// Next action of the creature
+    ///
+    /// `None` means the actor has not decided yet, eg. it waits for
+    /// the user's input.
+    fn get_action(&mut self, map : &map::Map, _ : &CreatureState) -> Option<Action>;
+
+    /// Creature `me` can see `p`
+    fn proceed_visible(&mut self, map : &map::Map, me : &CreatureState, p : Point);
+
+    /// LoS is about to be updated, `proceed_visible` calls will follow
+    fn forget_visible(&mut self) {
+    }
+
+    /// Noise was heard; its location is only approximate
+    fn hear(&mut self, _ : &map::Map, _ : &CreatureState, _ : &Noise) {
+    }
+
+    /// Creature was attacked by someone standing at `p`
+    fn attacked_from(&mut self, _ : Point) {
+    }
+
+    /// Sighting of an enemy to share with allies, if there's a new one,
+    /// and how far it's heard
+    fn take_sighting(&mut self) -> Option<(Position, uint)> {
+        None
+    }
+
+    /// An ally has seen an enemy at `pos`
+    fn told_about(&mut self, _ : Position) {
+    }
+
+    /// Leader gave an order
+    fn ordered(&mut self, _ : Order) {
+    }
+
+    /// Leader attacked a creature standing at `p`
+    fn leader_attacked(&mut self, _ : Point) {
+    }
+
+    /// Does the creature expect to be attacked
+    ///
+    /// Attacks on unaware creatures deal extra damage.
+    fn is_aware(&self) -> bool {
+        true
+    }
+}
+
+/// What companions were told to do by their leader
+#[deriving(PartialEq)]
+#[deriving(Show)]
+pub enum Order {
+    /// Stay close to the leader and defend it
+    Follow,
+    /// Stay in place and only fight what's in reach
+    Hold,
+    /// Attack the creature standing at given point
+    AttackAt(Point),
+}
+
+/// How aware of the enemy is the creature
+#[deriving(PartialEq)]
+#[deriving(PartialOrd)]
+#[deriving(Show)]
+pub enum Awareness {
+    Asleep,
+    Unaware,
+    Suspicious,
+    Alert,
+}
+
+/// Alertness needed to wake up and get suspicious
+static ALERTNESS_SUSPICIOUS : uint = 3;
+/// Alertness needed to get alert
+static ALERTNESS_ALERT : uint = 8;
+static ALERTNESS_MAX : uint = 20;
+
+/// How close an ally has to be to count as near
+static ALLY_DISTANCE : uint = 3;
+/// Distance from the enemy at which flanking creatures go straight for it
+static FLANK_DISTANCE : uint = 2;
+
+/// How far monsters tell each other about seeing an enemy
+static SHOUT_DISTANCE : uint = 10;
+/// How far a call for help is heard
+static CALL_DISTANCE : uint = 20;
+/// Actions between two calls for help
+static CALL_COOLDOWN : uint = 30;
+
+/// Distance Scouts try to keep from the enemy
+static SCOUT_DISTANCE : uint = 3;
+/// Actions spent waiting for help before fighting alone
+static PATIENCE : uint = 15;
+/// How far from home Heavies go to guard
+static GUARD_RADIUS : uint = 6;
+
+/// How close to the leader companions stay
+static FOLLOW_DISTANCE : uint = 2;
+/// How close to the leader an enemy has to be for companions to attack it
+static THREAT_DISTANCE : uint = 4;
+
+/// Actions spent searching for a lost enemy before giving up
+static SEARCH_TICKS : uint = 40;
+/// How far from where the enemy was lost to look for it
+static SEARCH_RADIUS : uint = 5;
+/// How many likely places to check when searching
+static SEARCH_LEADS : uint = 3;
+
+fn condition(holds : bool) -> Status {
+    if holds {
+        Success
+    } else {
+        Failure
+    }
+}
+
+/// Can `cr` step on `p`
+///
+/// Harmful tiles are avoided, unless `cr` is already standing on one
+/// and is desperate to get out.
+fn can_enter(map : &map::Map, cr : &CreatureState, p : Point) -> bool {
+    let tile = map.at(p);
+
+    if !tile.is_passable_for(cr.race) {
+        return false;
+    }
+
+    !tile.is_harmful() || map.at(cr.pos.p).is_harmful()
+}
+
+/// Is `p` a narrow passage that `race` can walk through
+///
+/// A chokepoint has exactly two passable neighbors, not touching each
+/// other.
+fn is_chokepoint(map : &map::Map, p : Point, race : Race) -> bool {
+    let tile = map.at(p);
+    if !tile.is_passable_type_for(race) || tile.is_harmful() {
+        return false;
+    }
+
+    let passable : Vec<Point> = p.neighbors().iter()
+        .map(|&n| map.wrap(n))
+        .filter(|&n| map.at(n).is_passable_type_for(race))
+        .collect();
+
+    passable.len() == 2 && map::distance(passable[0], passable[1]) > 1
+}
+
+/// Chokepoints no further than `radius` from `around`, closest first
+fn find_chokepoints(map : &map::Map, around : Point, radius : uint, race : Race) -> Vec<Point> {
+    let r = radius as int;
+    let mut found : Vec<(uint, Point)> = Vec::new();
+
+    for dx in range_inclusive(-r, r) {
+        for dy in range_inclusive(-r, r) {
+            let p = Point::new(around.x + dx, around.y + dy);
+            let distance = map::distance(around, p);
+            if distance > radius {
+                continue;
+            }
+
+            let p = map.wrap(p);
+            if is_chokepoint(map, p, race) {
+                found.push((distance, p));
+            }
+        }
+    }
+
+    found.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+    found.into_iter().map(|(_, p)| p).collect()
+}
+
+/// Looking for an enemy that got out of sight
+struct Search {
+    /// Turns left looking around
+    turns : uint,
+    /// Places the enemy could have gone, to check in order
+    leads : Vec<Point>,
+    /// Actions left before giving up
+    ticks : uint,
+}
+
+/// Result of running a behavior
+pub enum Status {
+    /// Behavior is done or its condition holds
+    Success,
+    /// Behavior can't be done now or its condition does not hold
+    Failure,
+    /// Behavior wants to perform an action
+    Running(Action),
+}
+
+/// Node of a behavior tree
+///
+/// A tree is turned into an `Actor` with `AIActor::with_tree`. Leaf
+/// nodes are either conditions (returning `Success` or `Failure`) or
+/// actions (returning `Running` or `Failure`).
+pub enum Behavior {
+    /// Run children in order until one of them fails or acts
+    Sequence(Vec<Behavior>),
+    /// Run children in order until one of them succeeds or acts
+    Select(Vec<Behavior>),
+    /// Is the creature asleep
+    Sleeping,
+    /// Succeed if the child fails and the other way around
+    Not(Box<Behavior>),
+    /// Has the enemy been seen during the last LoS update
+    SeesEnemy,
+    /// Is health below the race's `flee_health`
+    Hurt,
+    /// Are at least that many allies in sight and close by
+    AlliesNear(uint),
+    /// Was the enemy last seen within given distance from home
+    EnemyNearHome(uint),
+    /// Shout for help to allies further away than usual
+    CallAllies,
+    /// Back off when the enemy is closer than given distance, and stay
+    /// at that distance
+    KeepAway(uint),
+    /// Has the creature waited for help less than given number of
+    /// actions since the enemy came into view
+    Patient(uint),
+    /// When something's going on, stand at a chokepoint within given
+    /// distance from home
+    Guard(uint),
+    /// Look for the enemy where it was lost
+    Search,
+    /// Was the creature ordered to hold its place
+    Holding,
+    /// Was the creature ordered to attack someone
+    HasTarget,
+    /// Was the enemy last seen within given distance from the leader
+    EnemyNearLeader(uint),
+    /// Go back to the leader when further than given distance
+    StayClose(uint),
+    /// Melee the enemy if it's in reach
+    Attack,
+    /// Go after the enemy, to where it was last seen
+    Chase,
+    /// Check out where something suspicious was seen or heard
+    Investigate,
+    /// Get away from the enemy, as long as it's in sight
+    Flee,
+    /// Wander around, but don't go further than given distance from
+    /// where the creature started
+    Patrol(uint),
+    /// Wander around
+    Wander,
+    /// Wait
+    Idle,
+}
+
+/// Run, hide and get back when healed or not alone
+fn hurt_tree() -> Behavior {
+    Sequence(vec!(
+        Hurt,
+        Not(box AlliesNear(2)),
+        Select(vec!(
+            Sequence(vec!(SeesEnemy, Flee)),
+            Idle,
+        )),
+    ))
+}
+
+/// Behavior of monsters that don't need anything special
+pub fn default_tree() -> Behavior {
+    Select(vec!(
+        Sequence(vec!(Sleeping, Idle)),
+        hurt_tree(),
+        Attack,
+        Chase,
+        Search,
+        Investigate,
+        Wander,
+    ))
+}
+
+/// Scouts call for help and keep their distance
+pub fn scout_tree() -> Behavior {
+    Select(vec!(
+        Sequence(vec!(Sleeping, Idle)),
+        hurt_tree(),
+        Sequence(vec!(SeesEnemy, CallAllies)),
+        Sequence(vec!(
+            SeesEnemy,
+            Not(box AlliesNear(1)),
+            Patient(PATIENCE),
+            KeepAway(SCOUT_DISTANCE),
+        )),
+        Attack,
+        Chase,
+        Search,
+        Investigate,
+        Wander,
+    ))
+}
+
+/// Grunts advance only together, alone they hold and call for help
+/// until they lose patience
+pub fn grunt_tree() -> Behavior {
+    Select(vec!(
+        Sequence(vec!(Sleeping, Idle)),
+        hurt_tree(),
+        Attack,
+        Sequence(vec!(
+            SeesEnemy,
+            Not(box AlliesNear(1)),
+            Patient(PATIENCE),
+            Select(vec!(CallAllies, Idle)),
+        )),
+        Chase,
+        Search,
+        Investigate,
+        Wander,
+    ))
+}
+
+/// Companions follow the leader and fight its enemies
+pub fn companion_tree() -> Behavior {
+    Select(vec!(
+        hurt_tree(),
+        Sequence(vec!(Holding, Select(vec!(Attack, Idle)))),
+        Attack,
+        Sequence(vec!(HasTarget, Chase)),
+        Sequence(vec!(EnemyNearLeader(THREAT_DISTANCE), Chase)),
+        StayClose(FOLLOW_DISTANCE),
+        Idle,
+    ))
+}
+
+/// Heavies guard their area and don't let themselves be lured away
+pub fn heavy_tree() -> Behavior {
+    Select(vec!(
+        Sequence(vec!(Sleeping, Idle)),
+        hurt_tree(),
+        Attack,
+        Sequence(vec!(EnemyNearHome(GUARD_RADIUS), Chase)),
+        Search,
+        Guard(GUARD_RADIUS),
+        Patrol(GUARD_RADIUS / 2),
+    ))
 }
 
 pub struct AIActor {
+    tree : Behavior,
+    /// Where the creature was when it first acted
+    home : Option<Point>,
+    sees_enemy : bool,
     next_turn : Direction,
     next_turn_times: int,
-    last_player : Option<Point>,
+    last_enemy : Option<Point>,
+    /// Direction the enemy was facing when last seen
+    last_enemy_dir : Option<AbsoluteDirection>,
+    last_heard : Option<Point>,
+    /// Where the closest enemy was seen during the last LoS update
+    enemy_seen : Option<Position>,
+    /// Own sighting not yet shared with allies
+    sighting : Option<Position>,
+    /// Is the sighting a call for help
+    calling : bool,
+    /// Actions left until next call for help is possible
+    call_cooldown : uint,
+    /// Chokepoint to guard
+    post : Option<Point>,
+    order : Order,
+    /// Where the leader was seen during the last LoS update
+    leader_seen : Option<Point>,
+    /// Where the leader was last seen
+    last_leader : Option<Point>,
+    search : Option<Search>,
+    /// Side of the enemy to approach it from
+    flank : Direction,
+    /// Where allies were seen during the last LoS update
+    allies_seen : Vec<Point>,
+    allies : Vec<Point>,
+    /// Actions spent waiting for help since the enemy came into view
+    waited : uint,
+    awareness : Awareness,
+    alertness : uint,
+    /// Seeded from the game's RNG, so seeded games play out the same
+    rng : rand::XorShiftRng,
 }
 
 impl AIActor {
-    pub fn new() -> AIActor {
+    /// Actor with the personality of `race`
+    pub fn for_race<R : Rng>(race : Race, rng : &mut R) -> AIActor {
+        match race {
+            Scout => AIActor::with_tree(scout_tree(), rng),
+            Grunt => AIActor::with_tree(grunt_tree(), rng),
+            Heavy => AIActor::with_tree(heavy_tree(), rng),
+            Human => AIActor::player(default_tree(), rng),
+        }
+    }
+
+    /// Actor of a creature following the player
+    pub fn companion<R : Rng>(rng : &mut R) -> AIActor {
+        AIActor::player(companion_tree(), rng)
+    }
+
+    /// Actor for the player's side, which shouldn't be found sleeping
+    pub fn player<R : Rng>(tree : Behavior, rng : &mut R) -> AIActor {
+        let mut actor = AIActor::with_tree(tree, rng);
+        actor.awareness = Unaware;
+        actor
+    }
+
+    pub fn with_tree<R : Rng>(tree : Behavior, rng : &mut R) -> AIActor {
+        let mut rng : rand::XorShiftRng = rng.gen();
+
+        let awareness = if rng.gen_weighted_bool(4) {
+            Asleep
+        } else {
+            Unaware
+        };
+
+        let flank = match rng.gen_range(0u, 3) {
+            0 => Left,
+            1 => Right,
+            _ => Backward,
+        };
+
         AIActor{
+            tree: tree,
+            home: None,
+            sees_enemy: false,
             next_turn: Forward,
             next_turn_times: 0,
-            last_player: None,
+            last_enemy: None,
+            last_enemy_dir: None,
+            last_heard: None,
+            enemy_seen: None,
+            sighting: None,
+            calling: false,
+            call_cooldown: 0,
+            post: None,
+            order: Follow,
+            leader_seen: None,
+            last_leader: None,
+            search: None,
+            flank: flank,
+            allies_seen: Vec::new(),
+            allies: Vec::new(),
+            waited: 0,
+            awareness: awareness,
+            alertness: 0,
+            rng: rng,
+        }
+    }
+
+    fn raise_alertness(&mut self, by : uint) {
+        self.alertness = min(self.alertness + by, ALERTNESS_MAX);
+    }
+
+    /// Update awareness with what happened since the last action
+    ///
+    /// Seeing an enemy raises alertness, quicker when it's close. With
+    /// nothing going on, it slowly drops back.
+    fn update_awareness(&mut self, cr : &CreatureState) {
+        self.sees_enemy = false;
+        let leader_seen = self.leader_seen.take();
+        if leader_seen.is_some() {
+            self.last_leader = leader_seen;
+        }
+        if self.call_cooldown > 0 {
+            self.call_cooldown -= 1;
+        }
+        self.allies = mem::replace(&mut self.allies_seen, Vec::new());
+
+        match self.enemy_seen.take() {
+            Some(pos) if self.awareness != Asleep => {
+                self.sees_enemy = true;
+                let by = if map::distance(cr.pos.p, pos.p) <= 2 { 4 } else { 2 };
+                self.raise_alertness(by);
+                if self.alertness >= ALERTNESS_ALERT {
+                    self.search = None;
+                    self.last_enemy = Some(pos.p);
+                    self.last_enemy_dir = Some(pos.dir);
+                    self.sighting = Some(pos);
+                } else {
+                    self.last_heard = Some(pos.p);
+                }
+            },
+            _ => {
+                if self.alertness > 0 {
+                    self.alertness -= 1;
+                }
+            }
+        }
+
+        self.awareness = if self.awareness == Asleep && self.alertness < ALERTNESS_SUSPICIOUS {
+            Asleep
+        } else if self.alertness >= ALERTNESS_ALERT {
+            Alert
+        } else if self.alertness >= ALERTNESS_SUSPICIOUS {
+            Suspicious
+        } else {
+            Unaware
+        };
+
+        /* Searching creatures don't calm down */
+        if self.search.is_some() && self.awareness < Suspicious {
+            self.awareness = Suspicious;
+        }
+
+        if self.awareness == Unaware {
+            self.last_heard = None;
+        }
+
+        if !self.sees_enemy {
+            self.waited = 0;
+        }
+    }
+
+    /// Make sure the ordered target is chased, until it's gone
+    fn apply_order(&mut self, map : &map::Map, me : &CreatureState) {
+        let p = match self.order {
+            AttackAt(p) => map.wrap(p),
+            _ => return,
+        };
+
+        let hostile = map.at(p).creature.as_ref()
+            .and_then(|cr| cr.try_borrow())
+            .map_or(false, |cr| cr.is_alive() && me.faction.is_hostile_to(cr.faction()));
+
+        if hostile {
+            self.last_enemy = Some(p);
+            self.last_enemy_dir = None;
+            self.alertness = ALERTNESS_MAX;
+            self.awareness = Alert;
+        } else {
+            self.order = Follow;
+        }
+    }
+
+    /// Start searching for the enemy lost at `p`
+    ///
+    /// Look around first, then check the passages nearby, starting
+    /// with the ones in the direction the enemy was heading.
+    fn start_search(&mut self, map : &map::Map, me : &CreatureState, p : Point) {
+        let guess = match self.last_enemy_dir {
+            Some(dir) => map.wrap(map.wrap(p + dir) + dir),
+            None => p,
+        };
+
+        let mut leads : Vec<Point> = find_chokepoints(map, p, SEARCH_RADIUS, me.race)
+            .into_iter()
+            .filter(|&lead| lead != p)
+            .collect();
+        leads.sort_by(|&a, &b| map::distance(a, guess).cmp(&map::distance(b, guess)));
+        leads.truncate(SEARCH_LEADS);
+
+        self.search = Some(Search {
+            turns: 6,
+            leads: leads,
+            ticks: SEARCH_TICKS,
+        });
+    }
+
+    /// Forget targets that are no longer valid
+    fn forget_lost(&mut self, map : &map::Map, me : &CreatureState) {
+        match self.last_enemy {
+            Some(p) => {
+                let lost = me.pos.p == p || map.at(p).creature.as_ref()
+                    .and_then(|cr| cr.try_borrow())
+                    .map(|cr| !me.faction.is_hostile_to(cr.faction()))
+                    .unwrap_or(false);
+                if lost {
+                    self.last_enemy = None;
+                    self.start_search(map, me, p);
+                }
+            },
+            None => {}
+        }
+
+        if self.last_heard.is_some() && me.pos.p == self.last_heard.unwrap() {
+            self.last_heard = None;
+        }
+    }
+
+    fn run(&mut self, node : &Behavior, map : &map::Map, me : &CreatureState) -> Status {
+        match *node {
+            Sequence(ref children) => {
+                for child in children.iter() {
+                    match self.run(child, map, me) {
+                        Success => {},
+                        status => return status,
+                    }
+                }
+                Success
+            },
+            Select(ref children) => {
+                for child in children.iter() {
+                    match self.run(child, map, me) {
+                        Failure => {},
+                        status => return status,
+                    }
+                }
+                Failure
+            },
+            Not(ref child) => match self.run(&**child, map, me) {
+                Success => Failure,
+                Failure => Success,
+                status => status,
+            },
+            Sleeping => condition(self.awareness == Asleep),
+            SeesEnemy => condition(self.sees_enemy),
+            Hurt => condition(me.health_fraction() < me.race.flee_health()),
+            AlliesNear(n) => condition(
+                self.allies.iter().filter(|&&p| map::distance(me.pos.p, p) <= ALLY_DISTANCE).count() >= n
+                ),
+            EnemyNearHome(radius) => {
+                let home = self.home.unwrap_or(me.pos.p);
+                condition(self.last_enemy.map_or(false, |p| map::distance(home, p) <= radius))
+            },
+            CallAllies => {
+                if self.sighting.is_none() || self.call_cooldown > 0 {
+                    return Failure;
+                }
+                self.calling = true;
+                self.call_cooldown = CALL_COOLDOWN;
+                Running(Wait)
+            },
+            KeepAway(distance) => {
+                let p = match self.last_enemy {
+                    Some(p) => p,
+                    None => return Failure,
+                };
+                let now = map::distance(me.pos.p, p);
+                if now < distance {
+                    Running(self.flee(map, me, p))
+                } else if now == distance {
+                    Running(Wait)
+                } else {
+                    Failure
+                }
+            },
+            Patient(actions) => {
+                if self.waited >= actions {
+                    return Failure;
+                }
+                self.waited += 1;
+                Success
+            },
+            Guard(radius) => {
+                if self.awareness < Suspicious {
+                    return Failure;
+                }
+                if self.post.is_none() {
+                    let home = self.home.unwrap_or(me.pos.p);
+                    self.post = Some(
+                        find_chokepoints(map, home, radius, me.race).as_slice().head()
+                        .map_or(home, |&p| p)
+                        );
+                }
+                let post = self.post.unwrap();
+                if me.pos.p != post {
+                    Running(self.chase(map, me, post))
+                } else if self.rng.gen_weighted_bool(8) {
+                    Running(Turn(Right))
+                } else {
+                    Running(Wait)
+                }
+            },
+            Search => {
+                let mut search = match self.search.take() {
+                    Some(search) => search,
+                    None => return Failure,
+                };
+                if search.ticks == 0 {
+                    return Failure;
+                }
+                search.ticks -= 1;
+
+                let status = if search.turns > 0 {
+                    search.turns -= 1;
+                    Running(Turn(Right))
+                } else {
+                    if search.leads.as_slice().head() == Some(&me.pos.p) {
+                        search.leads.remove(0);
+                    }
+                    match search.leads.as_slice().head() {
+                        Some(&p) => Running(self.chase(map, me, p)),
+                        None => Running(self.roam_around(map, me)),
+                    }
+                };
+
+                self.search = Some(search);
+                status
+            },
+            Holding => condition(self.order == Hold),
+            HasTarget => condition(match self.order { AttackAt(_) => true, _ => false }),
+            EnemyNearLeader(distance) => condition(match (self.last_enemy, self.last_leader) {
+                (Some(enemy), Some(leader)) => map::distance(enemy, leader) <= distance,
+                _ => false,
+            }),
+            StayClose(distance) => {
+                let leader = match self.last_leader {
+                    Some(p) => p,
+                    None => return Failure,
+                };
+                if map::distance(me.pos.p, leader) > distance {
+                    Running(self.chase(map, me, leader))
+                } else if me.visible.contains(leader) {
+                    Failure
+                } else {
+                    /* Lost sight of the leader, look around */
+                    Running(Turn(Right))
+                }
+            },
+            Attack => {
+                if self.awareness < Suspicious || self.last_enemy.is_none() {
+                    return Failure;
+                }
+                for &dir in [Left,Forward,Right].iter() {
+                    let p = map.wrap(me.pos.p + (me.pos.dir + dir));
+                    if map.at(p).creature.as_ref()
+                        .and_then(|cr| cr.try_borrow())
+                        .map(|cr| me.faction.is_hostile_to(cr.faction()))
+                        .unwrap_or(false) {
+                            return Running(Melee(dir));
+                        }
+                }
+                Failure
+            },
+            Chase => {
+                if self.awareness < Suspicious {
+                    return Failure;
+                }
+                match self.last_enemy {
+                    Some(p) => {
+                        let p = self.approach_point(map, me, p);
+                        Running(self.chase(map, me, p))
+                    },
+                    None => Failure,
+                }
+            },
+            Investigate => {
+                if self.awareness < Suspicious {
+                    return Failure;
+                }
+                match self.last_heard {
+                    Some(p) => Running(self.chase(map, me, p)),
+                    None => Failure,
+                }
+            },
+            Flee => {
+                match self.last_enemy.or(self.last_heard) {
+                    Some(p) => Running(self.flee(map, me, p)),
+                    None => Failure,
+                }
+            },
+            Patrol(radius) => {
+                let home = self.home.unwrap_or(me.pos.p);
+                if map::distance(me.pos.p, home) > radius {
+                    Running(self.chase(map, me, home))
+                } else {
+                    Running(self.roam_around(map, me))
+                }
+            },
+            Wander => Running(self.roam_around(map, me)),
+            Idle => Running(Wait),
         }
     }
 
     fn chase(&mut self, map : &map::Map, cr : &CreatureState, p : Point) -> Action {
+        let hurry = cr.race == Scout && self.awareness == Alert;
+
+        match path::path_to(map, &path::Everything, cr.pos, p, cr.race) {
+            Ok(actions) => match actions.as_slice().head() {
+                Some(&Move(dir)) if hurry => Run(dir),
+                Some(&action) => action,
+                None => Wait,
+            },
+            Err(_) => self.steer(map, cr, p),
+        }
+    }
+
+    /// Steer towards `p` without planning
+    ///
+    /// Used when path finding gives up.
+    fn steer(&mut self, map : &map::Map, cr : &CreatureState, p : Point) -> Action {
         let rel = cr.pos.relative_wrapped(map, p);
 
         let atan2 = (rel.y as f32).atan2(rel.x as f32);
@@ -52,8 +827,8 @@ impl AIActor {
             };
             self.next_turn_times = 2;
 
-            if map.at(map.wrap(cr.pos.p + cr.pos.dir)).is_passable() {
-                if cr.race == Scout {
+            if can_enter(map, cr, map.wrap(cr.pos.p + cr.pos.dir)) {
+                if cr.race == Scout && self.awareness == Alert {
                     Run(Forward)
                 } else {
                     Move(Forward)
@@ -64,25 +839,74 @@ impl AIActor {
         }
     }
 
+    /// Where to go to get to the enemy at `p`
+    ///
+    /// From far away, go around to the enemy's `flank` side, so that
+    /// a group attacks from different directions.
+    fn approach_point(&self, map : &map::Map, me : &CreatureState, p : Point) -> Point {
+        let dir = match self.last_enemy_dir {
+            Some(dir) => dir,
+            None => return p,
+        };
+
+        if map::distance(me.pos.p, p) <= FLANK_DISTANCE {
+            return p;
+        }
+
+        let side = dir + self.flank;
+        let flank_p = map.wrap(map.wrap(p + side) + side);
+
+        if map.at(flank_p).is_passable_type_for(me.race) {
+            flank_p
+        } else {
+            p
+        }
+    }
+
+    /// Step away from `p`, or turn to be able to
+    fn flee(&mut self, map : &map::Map, cr : &CreatureState, p : Point) -> Action {
+        let now = map::distance(cr.pos.p, p);
+        let distance_after = |dir : AbsoluteDirection| {
+            let front = map.wrap(cr.pos.p + dir);
+            if can_enter(map, cr, front) {
+                map::distance(front, p)
+            } else {
+                0
+            }
+        };
+
+        if distance_after(cr.pos.dir) > now {
+            return if cr.race == Scout { Run(Forward) } else { Move(Forward) };
+        }
+
+        let left = distance_after(cr.pos.dir + Left);
+        let right = distance_after(cr.pos.dir + Right);
+
+        if left > right {
+            Turn(Left)
+        } else {
+            Turn(Right)
+        }
+    }
+
     fn roam_around(&mut self, map : &map::Map, cr : &CreatureState) -> Action {
         if self.next_turn_times > 0 {
             self.next_turn_times = self.next_turn_times - 1;
             Turn(self.next_turn)
         } else {
-            let mut rng = rand::task_rng();
             loop {
-                let dir = match rng.gen_range(0u, 6) {
+                let dir = match self.rng.gen_range(0u, 6) {
                     0|1 => Forward,
                     2 => Left,
                     3 => Right,
                     _ => return Wait
                 };
 
-                if !map.at(map.wrap(cr.pos.p + cr.pos.dir)).is_passable() {
+                if !can_enter(map, cr, map.wrap(cr.pos.p + cr.pos.dir)) {
                     return Turn(Right)
-                } else if map.at(map.wrap(cr.pos.p + (cr.pos.dir + dir))).is_passable()
-                    && map.at(map.wrap(cr.pos.p + (cr.pos.dir + dir) + (cr.pos.dir + dir))).is_passable()
-                        && !rng.gen_weighted_bool(8) {
+                } else if can_enter(map, cr, map.wrap(cr.pos.p + (cr.pos.dir + dir)))
+                    && can_enter(map, cr, map.wrap(cr.pos.p + (cr.pos.dir + dir) + (cr.pos.dir + dir)))
+                        && !self.rng.gen_weighted_bool(8) {
                             return Move(Forward)
                         } else {
                             match dir {
@@ -96,44 +920,131 @@ impl AIActor {
 }
 
 impl Actor for AIActor {
-    fn get_action(&mut self, map : &map::Map, me : &CreatureState) -> Action {
-        if self.last_player.is_some() {
-            if me.pos.p == self.last_player.unwrap() {
-                self.last_player = None;
-            } else if map.at(self.last_player.unwrap()).creature.as_ref()
-                .and_then(|cr| cr.try_borrow())
-                    .map(|cr| !cr.is_player())
-                    .unwrap_or(false) {
-                        self.last_player = None;
-                    }
+    fn get_action(&mut self, map : &map::Map, me : &CreatureState) -> Option<Action> {
+        if self.home.is_none() {
+            self.home = Some(me.pos.p);
         }
 
-        if self.last_player.is_some() {
-            for dir in [Left,Forward,Right].iter() {
-                let p = map.wrap(me.pos.p + (me.pos.dir + *dir));
-                if map.at(p).creature.as_ref()
-                    .and_then(|cr| cr.try_borrow())
-                        .map(|cr| cr.is_player())
-                        .unwrap_or(false) {
-                            return Melee(*dir);
-                        }
-            }
-        }
+        self.update_awareness(me);
+        self.apply_order(map, me);
+        self.forget_lost(map, me);
 
-        if self.last_player.is_some() {
-            let last_player = self.last_player.unwrap();
-            self.chase(map, me, last_player)
-        } else {
-            self.roam_around(map, me)
+        let tree = mem::replace(&mut self.tree, Idle);
+        let status = self.run(&tree, map, me);
+        self.tree = tree;
+
+        match status {
+            Running(action) => Some(action),
+            _ => Some(Wait),
         }
     }
 
-    fn proceed_visible(&mut self, map : &map::Map, p : Point) {
-        match map.at(p).creature.as_ref()
+    fn proceed_visible(&mut self, map : &map::Map, me : &CreatureState, p : Point) {
+        if p == me.pos.p {
+            return;
+        }
+
+        let (faction, pos, is_player) = match map.at(p).creature.as_ref()
             .and_then(|cr| cr.try_borrow())
-            .map(|cr| cr.is_player()) {
-                Some(true) => self.last_player = Some(p),
-                _=> {}
+            .map(|cr| (cr.faction(), *cr.pos(), cr.is_player())) {
+                Some(seen) => seen,
+                None => return,
+            };
+
+        if faction == me.faction && is_player {
+            self.leader_seen = Some(p);
+        }
+
+        if me.faction.is_hostile_to(faction) {
+            /* Keep an eye on the closest enemy */
+            let closer = self.enemy_seen.map_or(true, |seen| {
+                map::distance(me.pos.p, p) < map::distance(me.pos.p, seen.p)
+            });
+            if closer {
+                self.enemy_seen = Some(pos);
+            }
+        } else if faction == me.faction {
+            self.allies_seen.push(p);
+        }
+    }
+
+    fn forget_visible(&mut self) {
+        self.allies_seen.clear();
+    }
+
+    fn hear(&mut self, _ : &map::Map, me : &CreatureState, noise : &Noise) {
+        if me.faction.is_hostile_to(noise.faction) {
+            self.raise_alertness(noise.loudness);
+            if self.awareness != Asleep {
+                self.last_heard = Some(noise.p);
             }
+        }
+    }
+
+    fn attacked_from(&mut self, p : Point) {
+        self.alertness = ALERTNESS_MAX;
+        self.awareness = Alert;
+        self.last_enemy = Some(p);
+    }
+
+    fn ordered(&mut self, order : Order) {
+        /* Being given orders wakes anyone up */
+        self.order = order;
+        self.alertness = ALERTNESS_MAX;
+        self.awareness = Alert;
+    }
+
+    fn leader_attacked(&mut self, p : Point) {
+        if self.order == Follow {
+            self.order = AttackAt(p);
+        }
+    }
+
+    fn take_sighting(&mut self) -> Option<(Position, uint)> {
+        let distance = if self.calling { CALL_DISTANCE } else { SHOUT_DISTANCE };
+        self.calling = false;
+        self.sighting.take().map(|pos| (pos, distance))
+    }
+
+    fn told_about(&mut self, pos : Position) {
+        /* Sleepers only stir, like at a distant noise; sneaking up on
+         * them still works */
+        if self.awareness == Asleep {
+            self.raise_alertness(1);
+            return;
+        }
+
+        self.raise_alertness(ALERTNESS_ALERT);
+        self.awareness = Alert;
+        self.last_enemy = Some(pos.p);
+        self.last_enemy_dir = Some(pos.dir);
+    }
+
+    fn is_aware(&self) -> bool {
+        self.awareness >= Suspicious
+    }
+}
+
+/// Actor doing whatever was put in its queue
+///
+/// Used for the keyboard, but anything can fill the queue.
+pub struct InputActor {
+    queue : ActionQueue,
+}
+
+impl InputActor {
+    pub fn new(queue : ActionQueue) -> InputActor {
+        InputActor {
+            queue: queue,
+        }
+    }
+}
+
+impl Actor for InputActor {
+    fn get_action(&mut self, _ : &map::Map, _ : &CreatureState) -> Option<Action> {
+        self.queue.borrow_mut().pop_front()
+    }
+
+    fn proceed_visible(&mut self, _ : &map::Map, _ : &CreatureState, _ : Point) {
     }
 }
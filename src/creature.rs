@@ -1,19 +1,61 @@
 // Copyright 2014 Dawid Ciężarkiewicz
 // See LICENSE file for more information
 
-use ai::{Actor,AIActor};
+use ai::{Actor,Order};
+use fov;
 use game;
 use game::Action;
 use game::{Melee,Turn,Move,Run,Wait};
 use hex2d;
 use hex2d::{Left,Right,Forward,Backward};
 use hex2d::{Point,Position};
-use hex2d::AbsoluteDirection;
 use map;
 use map::Map;
+use map::PointSet;
+use memory::Memory;
+use noise::Noise;
 use map::TileType;
+use map::GlassWall;
+use effect::{Effect,Effects};
+use progress::{Progress,Perk,FastRunner,KeenEyes,Backstabber};
+use effect::{Stunned,Bleeding,Slowed,Blinded,Enraged};
+use light::AMBIENT_LIGHT;
+use std::cmp::max;
+use std::mem;
 use time;
 
+/// Light budget of LoS, reduced by `Tile::opaqueness` of every tile
+static LOS_BUDGET : int = 15;
+
+/// Ticks without getting hurt it takes to regenerate one health point
+static REGENERATION_TICKS : uint = 40;
+
+/// Damage multiplier for attacks on unaware creatures
+static SNEAK_ATTACK_MULTIPLIER : uint = 2;
+
+/// Light of the lantern carried by the player
+static LANTERN_LIGHT : uint = 6;
+
+/// Extra LoS budget with the `KeenEyes` perk
+static KEEN_EYES_LOS_BUDGET : int = 5;
+/// Damage multiplier for surprise attacks with the `Backstabber` perk
+static BACKSTABBER_MULTIPLIER : uint = 3;
+/// Levels it takes to get one more damage point
+static LEVELS_PER_DAMAGE : uint = 2;
+
+/// LoS budget of blinded creatures
+static BLINDED_LOS_BUDGET : int = 4;
+/// Extra damage dealt by enraged creatures
+static ENRAGED_DAMAGE : uint = 1;
+/// Extra action delay of slowed creatures
+static SLOWED_DELAY : uint = 2;
+/// Damage of a single hit that makes the target bleed
+static BLEEDING_DAMAGE : uint = 3;
+static STUNNED_TICKS : uint = 3;
+static BLEEDING_TICKS : uint = 15;
+static ENRAGED_TICKS : uint = 20;
+static BLINDED_TICKS : uint = 5;
+
 /// Race of the creature
 #[deriving(PartialEq)]
 #[deriving(Eq)]
@@ -25,6 +67,25 @@ pub enum Race {
 }
 
 impl Race {
+    /// Index of the race, eg. for per-race statistics
+    pub fn to_uint(&self) -> uint {
+        match *self {
+            Human => 0,
+            Scout => 1,
+            Grunt => 2,
+            Heavy => 3,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Human => "human",
+            Scout => "scout",
+            Grunt => "grunt",
+            Heavy => "heavy",
+        }
+    }
+
     pub fn max_health(&self) -> uint {
         match *self {
             Human => 4,
@@ -41,11 +102,99 @@ impl Race {
             Heavy => 3,
         }
     }
+
+    /// Fraction of max health below which the creature would rather
+    /// run than fight
+    pub fn flee_health(&self) -> f32 {
+        match *self {
+            Human => 0.0,
+            Scout => 0.75,
+            Grunt => 0.5,
+            Heavy => 0.25,
+        }
+    }
+
+    /// Light emitted by the creature itself
+    pub fn glow(&self) -> uint {
+        match *self {
+            Heavy => 2,
+            _ => 0,
+        }
+    }
+
+    /// Faction creatures of the race belong to, unless told otherwise
+    pub fn faction(&self) -> Faction {
+        match *self {
+            Human => Adventurers,
+            Scout|Grunt => Horde,
+            Heavy => Brutes,
+        }
+    }
+
+    /// Experience for killing a creature of the race
+    pub fn xp(&self) -> uint {
+        match *self {
+            Human => 5,
+            Scout => 2,
+            Grunt => 4,
+            Heavy => 8,
+        }
+    }
+
+    /// Heavy races can't swim
+    pub fn is_heavy(&self) -> bool {
+        match *self {
+            Grunt|Heavy => true,
+            _ => false,
+        }
+    }
+}
+
+/// Side a creature is on
+#[deriving(PartialEq)]
+#[deriving(Eq)]
+#[deriving(Show)]
+pub enum Faction {
+    /// The player and friends
+    Adventurers,
+    /// Monsters hunting in packs
+    Horde,
+    /// Monsters keeping to themselves, at war with the Horde
+    Brutes,
+    /// Animals minding their own business
+    Wildlife,
+}
+
+/// Who attacks whom, indexed by `Faction::to_uint` of the attacker
+/// and then of the target
+static HOSTILITY : [[bool, ..4], ..4] = [
+    /* Adventurers  Horde  Brutes  Wildlife */
+    [  false,       true,  true,   false ], /* Adventurers */
+    [  true,        false, true,   false ], /* Horde */
+    [  true,        true,  false,  true  ], /* Brutes */
+    [  false,       false, false,  false ], /* Wildlife */
+];
+
+impl Faction {
+    pub fn to_uint(&self) -> uint {
+        match *self {
+            Adventurers => 0,
+            Horde => 1,
+            Brutes => 2,
+            Wildlife => 3,
+        }
+    }
+
+    /// Do members of this faction attack members of `other` on sight
+    pub fn is_hostile_to(&self, other : Faction) -> bool {
+        HOSTILITY[self.to_uint()][other.to_uint()]
+    }
 }
 
 pub struct CreatureState {
-    pub visible: hex2d::Map<bool>,
-    pub known: hex2d::Map<bool>,
+    pub visible: PointSet,
+    /// Only creatures that need it remember what they've seen
+    pub memory: Option<Memory>,
 
     pub is_player : bool,
 
@@ -53,16 +202,33 @@ pub struct CreatureState {
     action_prev : Option<Action>,
     action_delay : uint,
     last_hit_ns: u64,
+    /// Tick of the last time health changed
+    health_tick: uint,
     last_attack_ns: u64,
     death_ns: u64,
 
     pub race : Race,
+    pub faction : Faction,
     health: int,
     alive : bool,
     damage : int,
     pub pos : Position,
     pub pos_prev : Position,
     pos_tiletype : TileType,
+    lantern : uint,
+    /// Current game tick
+    now : uint,
+    /// Tick of the last LoS update
+    los_tick : Option<uint>,
+
+    pub effects : Effects,
+    /// Only the player gets better with time
+    pub progress : Option<Progress>,
+
+    damage_dealt : uint,
+    damage_taken : uint,
+    /// Races of creatures killed
+    kills : Vec<Race>,
 }
 
 pub struct Creature {
@@ -71,10 +237,12 @@ pub struct Creature {
 }
 
 impl Creature {
-    pub fn new(map : &map::Map, pos : Position, player : bool, race : Race) -> Creature {
+    /// Creature controlled by given `actor`
+    pub fn with_actor(map : &map::Map, pos : Position, player : bool, race : Race,
+                      actor : Box<Actor+'static>) -> Creature {
         Creature {
             state: CreatureState::new(map, pos, player, race),
-            actor: box AIActor::new(),
+            actor: actor,
         }
     }
 
@@ -86,24 +254,75 @@ impl Creature {
         self.state.race
     }
 
-    #[allow(dead_code)]
+    pub fn faction(&self) -> Faction {
+        self.state.faction
+    }
+
+    /// Change sides, eg. for a creature not like the rest of its race
+    pub fn set_faction(&mut self, faction : Faction) {
+        self.state.faction = faction;
+    }
+
+    /// Would this creature attack `other` on sight
+    pub fn is_hostile_to(&self, other : &Creature) -> bool {
+        self.state.faction.is_hostile_to(other.state.faction)
+    }
+
     pub fn health(&self) -> uint {
-        let health = self.state.health;
+        self.state.health()
+    }
+
+    pub fn max_health(&self) -> uint {
+        self.state.max_health() as uint
+    }
+
+    pub fn progress<'a>(&'a self) -> Option<&'a Progress> {
+        self.state.progress.as_ref()
+    }
+
+    /// Continue with previously saved `progress`
+    pub fn set_progress(&mut self, progress : Progress) {
+        self.state.progress = Some(progress);
+        self.state.health = self.state.max_health();
+    }
+
+    /// Take `perk` for a level up, if there's one to take it for
+    pub fn choose_perk(&mut self, perk : Perk) -> bool {
+        self.state.progress.as_mut().map_or(false, |progress| progress.choose(perk))
+    }
+
 
-        if health < 0 {
-            return 0u;
+    /// Damage of a single hit
+    pub fn damage(&self) -> uint {
+        let damage = self.state.damage as uint + (self.state.level() - 1) / LEVELS_PER_DAMAGE;
+
+        if self.state.effects.has(Enraged) {
+            damage + ENRAGED_DAMAGE
+        } else {
+            damage
         }
+    }
 
-        return health as uint;
+    pub fn effects<'a>(&'a self) -> &'a Effects {
+        &self.state.effects
     }
 
-    #[allow(dead_code)]
-    pub fn max_health(&self) -> uint {
-        let health = self.state.race.max_health();
+    /// Put `effect` on this creature for `ticks`
+    pub fn add_effect(&mut self, effect : Effect, ticks : uint) {
+        self.state.effects.add(effect, ticks);
+    }
 
-        return health as uint;
+    /// Count down effects
+    ///
+    /// Returns damage they dealt, to be applied with `hurt`.
+    pub fn tick_effects(&mut self) -> uint {
+        self.state.effects.tick()
     }
 
+    /// Light emitted by the creature and everything it carries
+    pub fn light(&self) -> uint {
+        max(self.state.race.glow(), self.state.lantern)
+    }
 
     pub fn p<'a>(&'a self) -> &'a Point {
         &self.state.pos.p
@@ -119,29 +338,59 @@ impl Creature {
     }
 
     pub fn knows(&self, p : Point) ->  bool {
-        *self.state.known.at(p)
+        self.state.memory.as_ref().map_or(false, |memory| memory.knows(p))
+    }
+
+    /// Tiles in sight
+    pub fn visible<'a>(&'a self) -> &'a PointSet {
+        &self.state.visible
+    }
+
+    pub fn memory<'a>(&'a self) -> Option<&'a Memory> {
+        self.state.memory.as_ref()
     }
 
     pub fn sees(&self, p : Point) ->  bool {
-        *self.state.visible.at(p)
+        self.state.visible.contains(p)
     }
 
     pub fn pos_set(&mut self, map : &Map, pos : Position) {
+        let entered = self.state.pos.p != pos.p;
         self.state.pos = pos;
         self.state.pos_tiletype = map.at(pos.p).tiletype;
 
-        self.update_los(map);
+        /* Turning in place doesn't make terrain act again */
+        if entered {
+            match self.state.pos_tiletype.effect() {
+                Some((effect, ticks)) => self.add_effect(effect, ticks),
+                None => {}
+            }
+        }
+
+        self.refresh_los(map);
     }
 
     pub fn pos_prev_set(&mut self, _ : &Map, pos : Position) {
         self.state.pos_prev = pos;
     }
 
-    pub fn action_set(&mut self, action : Action) {
-        self.state.action_set(action);
+    /// Let `actor` control this creature from now on
+    ///
+    /// Returns the previous actor.
+    pub fn set_actor(&mut self, actor : Box<Actor+'static>) -> Box<Actor+'static> {
+        mem::replace(&mut self.actor, actor)
+    }
+
+    /// Let the creature know the current tick
+    ///
+    /// Called at the start of every tick, before anything can update
+    /// its LoS.
+    pub fn set_now(&mut self, now : uint) {
+        self.state.now = now;
     }
 
     pub fn tick(&mut self) -> Option<Action> {
+        self.state.regenerate();
         self.state.tick()
     }
 
@@ -162,73 +411,75 @@ impl Creature {
         self.state.death_ns
     }
 
-    pub fn needs_action(&self) -> bool {
-        self.state.action_cur.is_none() && self.state.action_delay == 0
+    /// Total damage dealt to other creatures
+    pub fn damage_dealt(&self) -> uint {
+        self.state.damage_dealt
     }
 
-    // Very hacky, recursive LoS algorithm
-    fn do_los(
-        &mut self,
-        map : &map::Map,
-        p: hex2d::Point, main_dir : hex2d::AbsoluteDirection,
-        dir : Option<hex2d::AbsoluteDirection>,
-        pdir : Option<hex2d::AbsoluteDirection>,
-        light: int
-        ) {
+    /// Total damage taken, from attacks and terrain
+    pub fn damage_taken(&self) -> uint {
+        self.state.damage_taken
+    }
 
-        self.mark_visible(map, p);
+    /// Races of all the creatures killed by this one
+    pub fn kills<'a>(&'a self) -> &'a [Race] {
+        self.state.kills.as_slice()
+    }
 
-        let mut light = light;
+    /// Action decided on, but not performed yet
+    pub fn action(&self) -> Option<Action> {
+        self.state.action_cur
+    }
 
-        let opaqueness = map.at(p).opaqueness();
-        light = light - opaqueness as int;
+    /// Ticks left until the creature can act again
+    pub fn action_delay(&self) -> uint {
+        self.state.action_delay
+    }
 
-        if light < 0 {
-            return;
-        }
+    pub fn needs_action(&self) -> bool {
+        self.state.action_cur.is_none() && self.state.action_delay == 0
+    }
 
-        let neighbors = match (dir, pdir) {
-            (Some(dir), Some(pdir)) => {
-                if dir == pdir {
-                    vec!(dir)
-                } else {
-                    vec!(dir, pdir)
-                }
-            },
-            (Some(dir), None) => {
-                if main_dir == dir {
-                    vec!(dir, dir + Left, dir + Right)
-                } else {
-                    vec!(dir, main_dir)
-                }
-            },
-            _ => {
-                vec!(main_dir, main_dir + Left, main_dir + Right)
-            }
-        };
+    /// Is `p` lit enough to be seen from here
+    ///
+    /// Adjacent tiles can always be made out, even in the dark.
+    fn can_see_at(&self, map : &Map, p : Point) -> bool {
+        map.at(p).light > AMBIENT_LIGHT || map::distance(*self.p(), p) <= 1
+    }
 
-        for &d in neighbors.iter() {
-            let n = map.wrap(p + d);
-            match dir {
-                Some(_) => {
-                    self.do_los(map, n, d, Some(d), dir, light);
-                },
-                None => {
-                    self.do_los(map, n, main_dir, Some(d), dir, light);
-                }
-            };
+    /// Update LoS, unless already done this tick
+    ///
+    /// A player thinking what to do gets asked again every frame, while
+    /// nothing changes.
+    pub fn update_los(&mut self, map : &Map) {
+        if self.state.los_tick == Some(self.state.now) {
+            return;
         }
+        self.refresh_los(map);
     }
 
-    pub fn update_los(&mut self, map : &Map) {
+    /// Update LoS after the creature moved or the map changed
+    pub fn refresh_los(&mut self, map : &Map) {
+        self.state.los_tick = Some(self.state.now);
         self.forget_visible(map);
         for &p in self.p().neighbors().iter() {
             let p = map.wrap(p);
             self.mark_known(map, p);
         }
-        let p = self.state.pos.p;
-        let dir = self.state.pos.dir;
-        self.do_los(map,p, dir, None, None, 15);
+
+        let pos = self.state.pos;
+        let budget = if self.state.effects.has(Blinded) {
+            BLINDED_LOS_BUDGET
+        } else if self.state.has_perk(KeenEyes) {
+            LOS_BUDGET + KEEN_EYES_LOS_BUDGET
+        } else {
+            LOS_BUDGET
+        };
+        for &p in fov::field_of_view(map, pos, budget).iter() {
+            if self.can_see_at(map, p) {
+                self.mark_visible(map, p);
+            }
+        }
     }
 
     fn mark_known(&mut self, map : &Map, p : hex2d::Point) {
@@ -239,30 +490,113 @@ impl Creature {
         self.state.mark_visible(map, p);
 
         let Creature {
+            ref state,
             ref mut actor,
             ..
         } = *self;
 
-        actor.proceed_visible(map, p);
+        actor.proceed_visible(map, state, p);
     }
 
 
     pub fn forget_visible(&mut self, map : &Map) {
         self.state.forget_visible(map);
+        self.actor.forget_visible();
+    }
+
+    /// This creature has heard a noise
+    pub fn hear(&mut self, map : &Map, noise : &Noise) {
+        let Creature {
+            ref state,
+            ref mut actor,
+            ..
+        } = *self;
+
+        actor.hear(map, state, noise);
+    }
+
+    /// New sighting of an enemy to tell allies about, and how far
+    pub fn take_sighting(&mut self) -> Option<(Position, uint)> {
+        self.actor.take_sighting()
+    }
+
+    /// An ally has told this creature about an enemy at `pos`
+    pub fn told_about(&mut self, pos : Position) {
+        self.actor.told_about(pos);
+    }
+
+    /// Leader gave this creature an order
+    pub fn ordered(&mut self, order : Order) {
+        self.actor.ordered(order);
+    }
+
+    /// Leader attacked a creature at `p`
+    pub fn leader_attacked(&mut self, p : Point) {
+        self.actor.leader_attacked(p);
     }
 
     /// This creature has been attacked some other creature
-    pub fn attacked_by(&mut self, cr : &Creature) {
+    ///
+    /// Returns the damage taken.
+    pub fn attacked_by(&mut self, cr : &Creature) -> uint {
+        let aware = self.actor.is_aware();
+        let damage = if aware {
+            cr.damage()
+        } else if cr.state.has_perk(Backstabber) {
+            cr.damage() * BACKSTABBER_MULTIPLIER
+        } else {
+            cr.damage() * SNEAK_ATTACK_MULTIPLIER
+        };
+
+        self.actor.attacked_from(*cr.p());
+        self.hurt(damage);
+
+        if !aware {
+            self.add_effect(Stunned, STUNNED_TICKS);
+        }
+        if damage >= BLEEDING_DAMAGE {
+            self.add_effect(Bleeding, BLEEDING_TICKS);
+        }
+        if self.state.race == Grunt {
+            self.add_effect(Enraged, ENRAGED_TICKS);
+        }
+
+        damage
+    }
+
+    /// This creature has taken damage
+    pub fn hurt(&mut self, damage : uint) {
         self.state.last_hit_ns = time::precise_time_ns();
-        self.state.health = self.state.health - cr.state.damage;
+        self.state.health_tick = self.state.now;
+        self.state.health = self.state.health - damage as int;
+        self.state.damage_taken += damage;
         if self.state.health <= 0 {
             self.die();
         }
     }
 
-    /// This creature has attacked some other creature
-    pub fn attacked(&mut self, _ : &Creature) {
+    /// This creature has attacked some other creature, dealing `damage`
+    pub fn attacked(&mut self, target : &Creature, damage : uint) {
         self.state.last_attack_ns = time::precise_time_ns();
+        self.state.damage_dealt += damage;
+        if !target.is_alive() {
+            self.state.kills.push(target.race());
+
+            let levels = self.state.progress.as_mut()
+                .map_or(0, |progress| progress.gain(target.race().xp()));
+            /* Levelling up heals the new health */
+            self.state.health += levels as int;
+        }
+    }
+
+    /// This creature has smashed a tile of type `tiletype`
+    ///
+    /// Shards of smashed glass get into the eyes.
+    pub fn attacked_tile(&mut self, tiletype : TileType) {
+        self.state.last_attack_ns = time::precise_time_ns();
+        if tiletype == GlassWall {
+            self.add_effect(Blinded, BLINDED_TICKS);
+        }
     }
 
     fn die(&mut self) {
@@ -274,7 +608,10 @@ impl Creature {
         self.state.alive
     }
 
-    pub fn update_action(&mut self, map : &map::Map) {
+    /// Ask the actor for the next action
+    ///
+    /// Returns `false` if the actor has not decided yet.
+    pub fn update_action(&mut self, map : &map::Map) -> bool {
 
         let Creature {
             ref mut state,
@@ -282,30 +619,88 @@ impl Creature {
             ..
         } = *self;
 
-        let action = actor.get_action(map, state);
-        state.action_set(action);
+        match actor.get_action(map, state) {
+            Some(action) => {
+                state.action_set(action);
+                true
+            },
+            None => false,
+        }
     }
 }
 
 impl CreatureState {
     pub fn new(map : &map::Map, pos : Position, is_player : bool, race : Race) -> CreatureState {
         CreatureState {
-            visible: map.clone(false),
-            known: map.clone(false),
+            visible: PointSet::new(map),
+            memory: if is_player { Some(Memory::new(map)) } else { None },
             action_cur : None,
             action_prev : None,
             action_delay: 0,
             is_player: is_player,
             race: race,
+            faction: race.faction(),
             health: race.max_health() as int,
             damage: race.damage() as int,
             alive: true,
             pos: pos,
             pos_prev: pos,
             pos_tiletype: map.at(pos.p).tiletype,
+            lantern: if is_player { LANTERN_LIGHT } else { 0 },
+            now: 0,
+            los_tick: None,
             last_hit_ns: 0,
+            health_tick: 0,
             last_attack_ns: 0,
             death_ns: 0,
+            effects: Effects::new(),
+            progress: if is_player { Some(Progress::new()) } else { None },
+            damage_dealt: 0,
+            damage_taken: 0,
+            kills: Vec::new(),
+        }
+    }
+
+    /// Level of the creature; those that don't progress stay at 1
+    pub fn level(&self) -> uint {
+        self.progress.as_ref().map_or(1, |progress| progress.level)
+    }
+
+    pub fn has_perk(&self, perk : Perk) -> bool {
+        self.progress.as_ref().map_or(false, |progress| progress.has(perk))
+    }
+
+    pub fn health(&self) -> uint {
+        if self.health < 0 {
+            0
+        } else {
+            self.health as uint
+        }
+    }
+
+    /// Current game tick
+    pub fn now(&self) -> uint {
+        self.now
+    }
+
+    /// Max health of the race, plus one for every level gained
+    pub fn max_health(&self) -> int {
+        (self.race.max_health() + self.level() - 1) as int
+    }
+
+    pub fn health_fraction(&self) -> f32 {
+        self.health as f32 / self.max_health() as f32
+    }
+
+    /// Slowly heal when not hurt for a while
+    fn regenerate(&mut self) {
+        if self.health >= self.max_health() {
+            return;
+        }
+
+        if self.now >= self.health_tick + REGENERATION_TICKS {
+            self.health += 1;
+            self.health_tick = self.now;
         }
     }
 
@@ -314,6 +709,10 @@ impl CreatureState {
     }
 
     pub fn tick(&mut self) -> Option<Action> {
+        if self.effects.has(Stunned) {
+            return None;
+        }
+
         if self.action_delay > 0 {
             self.action_delay -= 1;
             None
@@ -329,9 +728,16 @@ impl CreatureState {
     }
 
     fn action_delay(&self, action : Action) -> uint {
+        /* Can't run through water */
+        let action = match action {
+            Run(dir) if !self.pos_tiletype.allows_running() => Move(dir),
+            action => action,
+        };
+
         let delay = match action {
             Run(Forward)|Run(Left)|Run(Right) => match self.action_prev {
-                Some(Run(Forward))|Some(Run(Left))|Some(Run(Right)) => 2,
+                Some(Run(Forward))|Some(Run(Left))|Some(Run(Right))
+                    if !self.has_perk(FastRunner) => 2,
                 _ => 1,
             },
             Turn(_) => 1,
@@ -343,24 +749,40 @@ impl CreatureState {
         };
 
         /* Terrain modifier */
-        match action {
+        let delay = match action {
             Run(_)|Move(_) => {
                 delay + self.pos_tiletype.move_delay()
             },
             _ => delay,
+        };
+
+        if self.effects.has(Slowed) {
+            delay + SLOWED_DELAY
+        } else {
+            delay
         }
     }
 
-    fn mark_visible(&mut self, _: &Map, p : Point) {
-        *self.visible.mut_at(p) = true;
-        *self.known.mut_at(p) = true;
+    fn mark_visible(&mut self, map : &Map, p : Point) {
+        self.visible.insert(p);
+        self.mark_known(map, p);
+
+        let now = self.now;
+        match self.memory {
+            Some(ref mut memory) => memory.remember_creature_at(map, p, now),
+            None => {}
+        }
     }
 
-    fn mark_known(&mut self, _: &Map, p : Point) {
-        *self.known.mut_at(p) = true;
+    fn mark_known(&mut self, map : &Map, p : Point) {
+        let now = self.now;
+        match self.memory {
+            Some(ref mut memory) => memory.remember_tile(map, p, now),
+            None => {}
+        }
     }
 
-    pub fn forget_visible(&mut self, map : &Map) {
-        self.visible = map.clone(false);
+    pub fn forget_visible(&mut self, _ : &Map) {
+        self.visible.clear();
     }
 }
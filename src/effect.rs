@@ -0,0 +1,79 @@
+// Copyright 2014 Dawid Ciężarkiewicz
+// See LICENSE file for more information
+
+use std::cmp::max;
+use std::slice::Items;
+
+/// Ticks between two damage points of `Bleeding`
+static BLEEDING_INTERVAL : uint = 5;
+
+/// Temporary condition of a creature
+#[deriving(PartialEq)]
+#[deriving(Eq)]
+#[deriving(Show)]
+pub enum Effect {
+    /// Can't act
+    Stunned,
+    /// Loses health every few ticks
+    Bleeding,
+    /// Actions take longer
+    Slowed,
+    /// Sees only what's close
+    Blinded,
+    /// Hits harder
+    Enraged,
+}
+
+/// Effects on a creature, with ticks left until each wears off
+pub struct Effects {
+    active : Vec<(Effect, uint)>,
+}
+
+impl Effects {
+    pub fn new() -> Effects {
+        Effects {
+            active: Vec::new(),
+        }
+    }
+
+    /// Put `effect` on for `ticks`, or longer if it's already on
+    pub fn add(&mut self, effect : Effect, ticks : uint) {
+        for entry in self.active.iter_mut() {
+            let (e, left) = *entry;
+            if e == effect {
+                *entry = (e, max(left, ticks));
+                return;
+            }
+        }
+
+        self.active.push((effect, ticks));
+    }
+
+    pub fn has(&self, effect : Effect) -> bool {
+        self.active.iter().any(|&(e, _)| e == effect)
+    }
+
+    pub fn iter(&self) -> Items<(Effect, uint)> {
+        self.active.iter()
+    }
+
+    /// Count down all effects, dropping the ones that wore off
+    ///
+    /// Returns damage taken during this tick.
+    pub fn tick(&mut self) -> uint {
+        let mut damage = 0;
+
+        for entry in self.active.iter_mut() {
+            let (effect, left) = *entry;
+            let left = if left > 0 { left - 1 } else { 0 };
+            if effect == Bleeding && left % BLEEDING_INTERVAL == 0 {
+                damage += 1;
+            }
+            *entry = (effect, left);
+        }
+
+        self.active.retain(|&(_, left)| left > 0);
+
+        damage
+    }
+}
@@ -0,0 +1,317 @@
+// Copyright 2014 Dawid Ciężarkiewicz
+// See LICENSE file for more information
+
+//! Field of view
+//!
+//! Symmetric shadowcasting adapted to hex grid. Area in front of the
+//! viewer is split into sextants, each scanned ring by ring. A sextant is
+//! a triangle between two neighboring directions; tile `col` of ring
+//! `depth` lies at `depth` steps in the first direction and `col` steps
+//! along the ring towards the second one, so lines through the viewer are
+//! lines of constant `col / depth`, just like in square shadowcasting.
+//!
+//! Instead of tiles being either transparent or not, every tile reduces
+//! the light budget by its `Tile::opaqueness`. Consecutive tiles leaving
+//! the same budget behind are scanned together, so partially transparent
+//! tiles (glass walls, creatures) cast partial shadows.
+//!
+//! Tiles nobody can stand on (walls) are visible when any part of them is
+//! lit. Every other tile, including one where the budget runs out, is
+//! visible only when its center is lit, so if `a` sees `b`, `b` sees `a`.
+
+use hex2d::{Point, Position, AbsoluteDirection};
+use hex2d::{Left, Right};
+use map::{Map,PointSet};
+use std::iter::range_inclusive;
+
+struct Sextant {
+    origin : Point,
+    /// Offset of one step away from the origin
+    forward : Point,
+    /// Offset of one step along the ring
+    side : Point,
+    /// Is the last column (`col == depth`) part of this sextant
+    ///
+    /// Neighboring sextants share it, only one of them returns it.
+    owns_end : bool,
+}
+
+impl Sextant {
+    fn new(origin : Point, dir : AbsoluteDirection, owns_end : bool) -> Sextant {
+        let zero = Point::new(0, 0);
+        Sextant {
+            origin: origin,
+            forward: zero + dir,
+            side: zero + (dir + Right + Right),
+            owns_end: owns_end,
+        }
+    }
+
+    fn owns(&self, depth : uint, col : int) -> bool {
+        self.owns_end || col < depth as int
+    }
+
+    fn tile(&self, depth : uint, col : int) -> Point {
+        let depth = depth as int;
+        Point::new(
+            self.origin.x + self.forward.x * depth + self.side.x * col,
+            self.origin.y + self.forward.y * depth + self.side.y * col,
+            )
+    }
+}
+
+/// Part of a ring that is still lit
+struct Row {
+    depth : uint,
+    start : f32,
+    end : f32,
+    budget : int,
+}
+
+impl Row {
+    fn min_col(&self) -> int {
+        (self.depth as f32 * self.start + 0.5).floor() as int
+    }
+
+    fn max_col(&self) -> int {
+        (self.depth as f32 * self.end - 0.5).ceil() as int
+    }
+
+    /// Is the center of tile `col` inside the lit part
+    ///
+    /// Only such tiles are visible (unless they block light), which makes
+    /// the result symmetric.
+    fn is_symmetric(&self, col : int) -> bool {
+        let col = col as f32;
+        let depth = self.depth as f32;
+        col >= depth * self.start && col <= depth * self.end
+    }
+
+    fn slope(&self, col : int) -> f32 {
+        (2 * col - 1) as f32 / (2 * self.depth) as f32
+    }
+
+    fn next(&self, start : f32, end : f32, budget : int) -> Row {
+        Row {
+            depth: self.depth + 1,
+            start: start,
+            end: end,
+            budget: budget,
+        }
+    }
+}
+
+fn scan(map : &Map, sextant : &Sextant, row : Row, visible : &mut PointSet) {
+    /* Start slope of the current run of tiles and budget left behind them */
+    let mut run : Option<(f32, int)> = None;
+
+    for col in range_inclusive(row.min_col(), row.max_col()) {
+        let p = map.wrap(sextant.tile(row.depth, col));
+        let tile = map.at(p);
+        let left = row.budget - tile.opaqueness() as int;
+
+        /* Tiles partially lit are scanned by both rows they belong to,
+         * the set keeps them from being returned twice */
+        if sextant.owns(row.depth, col)
+            && ((left < 0 && !tile.is_passable_type()) || row.is_symmetric(col)) {
+            visible.insert(p);
+        }
+
+        run = match run {
+            None => Some((row.start, left)),
+            Some((start, prev)) if prev != left => {
+                if prev >= 0 {
+                    scan(map, sextant, row.next(start, row.slope(col), prev), visible);
+                }
+                Some((row.slope(col), left))
+            },
+            run => run,
+        };
+    }
+
+    match run {
+        Some((start, left)) if left >= 0 => {
+            scan(map, sextant, row.next(start, row.end, left), visible);
+        },
+        _ => {}
+    }
+}
+
+/// Tiles visible from `pos` with a given light `budget`
+///
+/// Viewer sees a 120 degree cone in front of it. Every tile is returned
+/// once.
+pub fn field_of_view(map : &Map, pos : Position, budget : int) -> Vec<Point> {
+    let mut visible = PointSet::new(map);
+    visible.insert(map.wrap(pos.p));
+
+    let budget = budget - map.at(map.wrap(pos.p)).opaqueness() as int;
+    if budget >= 0 {
+        /* The column between the two sextants goes to the second one */
+        for &(dir, owns_end) in [(pos.dir + Left, false), (pos.dir, true)].iter() {
+            let sextant = Sextant::new(pos.p, dir, owns_end);
+            let row = Row {
+                depth: 1,
+                start: 0.0,
+                end: 1.0,
+                budget: budget,
+            };
+            scan(map, &sextant, row, &mut visible);
+        }
+    }
+
+    visible.iter().map(|&p| p).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::field_of_view;
+    use ai::AIActor;
+    use creature::{Creature, Grunt};
+    use hex2d;
+    use hex2d::{Point, Position, AbsoluteDirection};
+    use hex2d::{North, Left, Right, Backward};
+    use map;
+    use map::{Map, Tile, TileType, Floor, Wall, GlassWall};
+    use std::cell::RefCell;
+    use std::rand;
+    use std::rc::Rc;
+
+    static BUDGET : int = 6;
+
+    fn center() -> Point {
+        Point::new(15, 15)
+    }
+
+    fn floor_map() -> Box<Map> {
+        box hex2d::Map::new(30, 30, Tile {
+            tiletype: Floor,
+            creature: None,
+            damage: 0,
+            light: 0,
+        })
+    }
+
+    /// `n` steps from `p` towards `dir`
+    fn walk(p : Point, dir : AbsoluteDirection, n : uint) -> Point {
+        range(0, n).fold(p, |p, _| p + dir)
+    }
+
+    fn set(map : &mut Map, p : Point, tiletype : TileType) {
+        map.mut_at(p).tiletype = tiletype;
+    }
+
+    fn sees(map : &Map, from : Position, p : Point, budget : int) -> bool {
+        field_of_view(map, from, budget).contains(&map.wrap(p))
+    }
+
+    /// Would `from` see `p` facing any direction
+    fn sees_around(map : &Map, from : Point, p : Point, budget : int) -> bool {
+        range(0u, 6).any(|i| {
+            let dir = range(0, i).fold(North, |dir, _| dir + Right);
+            sees(map, Position::new(from, dir), p, budget)
+        })
+    }
+
+    #[test]
+    fn every_tile_once() {
+        let mut map = floor_map();
+        set(&mut *map, walk(center(), North, 2), Wall);
+        set(&mut *map, walk(center(), North + Right, 2), GlassWall);
+
+        let mut dir = North;
+        for _ in range(0u, 6) {
+            let visible = field_of_view(&*map, Position::new(center(), dir), 15);
+            for (i, p) in visible.iter().enumerate() {
+                assert!(!visible.slice_from(i + 1).contains(p));
+            }
+            dir = dir + Right;
+        }
+    }
+
+    #[test]
+    fn symmetric() {
+        let mut map = floor_map();
+        let c = center();
+        set(&mut *map, walk(c, North, 2), Wall);
+        set(&mut *map, walk(walk(c, North + Right, 3), North, 1), Wall);
+        set(&mut *map, walk(c, North + Backward + Right, 2), Wall);
+        set(&mut *map, walk(walk(c, North + Backward, 2), North + Right + Right, 1), Wall);
+        set(&mut *map, walk(c, North + Right + Right, 2), GlassWall);
+        set(&mut *map, walk(c, North + Left, 3), GlassWall);
+        set(&mut *map, walk(walk(c, North, 1), North + Right, 2), GlassWall);
+
+        let mut points = Vec::new();
+        map.for_each_point(|p| {
+            if map::distance(p, c) <= 5 && map.at(p).is_passable_type() {
+                points.push(p);
+            }
+        });
+
+        for &a in points.iter() {
+            for &b in points.iter() {
+                assert_eq!(sees_around(&*map, a, b, 8), sees_around(&*map, b, a, 8));
+            }
+        }
+    }
+
+    #[test]
+    fn facing_cone() {
+        let map = floor_map();
+        let pos = Position::new(center(), North);
+
+        assert!(sees(&*map, pos, walk(center(), North, 3), BUDGET));
+        assert!(sees(&*map, pos, walk(center(), North + Left, 3), BUDGET));
+        assert!(sees(&*map, pos, walk(center(), North + Right, 3), BUDGET));
+        assert!(!sees(&*map, pos, walk(center(), North + Backward, 3), BUDGET));
+        assert!(!sees(&*map, pos, walk(center(), North + Right + Right, 3), BUDGET));
+        assert!(!sees(&*map, pos, walk(center(), North + Left + Left, 3), BUDGET));
+    }
+
+    #[test]
+    fn budget_limits_range() {
+        let map = floor_map();
+        let pos = Position::new(center(), North);
+
+        assert!(sees(&*map, pos, walk(center(), North, 6), BUDGET));
+        assert!(!sees(&*map, pos, walk(center(), North, 7), BUDGET));
+    }
+
+    #[test]
+    fn wall_casts_shadow() {
+        let mut map = floor_map();
+        set(&mut *map, walk(center(), North, 2), Wall);
+        let pos = Position::new(center(), North);
+
+        assert!(sees(&*map, pos, walk(center(), North, 2), BUDGET));
+        assert!(!sees(&*map, pos, walk(center(), North, 3), BUDGET));
+        assert!(!sees(&*map, pos, walk(center(), North, 4), BUDGET));
+        assert!(sees(&*map, pos, walk(center(), North + Right, 4), BUDGET));
+    }
+
+    #[test]
+    fn glass_blocks_partially() {
+        let mut map = floor_map();
+        set(&mut *map, walk(center(), North, 1), GlassWall);
+        let pos = Position::new(center(), North);
+
+        assert!(sees(&*map, pos, walk(center(), North, 1), BUDGET));
+        assert!(sees(&*map, pos, walk(center(), North, 4), BUDGET));
+        assert!(!sees(&*map, pos, walk(center(), North, 5), BUDGET));
+    }
+
+    #[test]
+    fn creature_blocks_partially() {
+        let mut map = floor_map();
+        let p = walk(center(), North, 1);
+        let cr = Creature::with_actor(&*map, Position::new(p, North), false, Grunt,
+                                      box AIActor::for_race(Grunt, &mut rand::task_rng()));
+        map.mut_at(p).creature = Some(Rc::new(RefCell::new(cr)));
+        let pos = Position::new(center(), North);
+
+        assert!(sees(&*map, pos, p, BUDGET));
+        assert!(sees(&*map, pos, walk(center(), North, 2), BUDGET));
+        assert!(!sees(&*map, pos, walk(center(), North, 3), BUDGET));
+        assert!(sees(&*map, pos, walk(center(), North + Right, 3), BUDGET));
+    }
+}
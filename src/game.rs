@@ -1,31 +1,51 @@
 // Copyright 2014 Dawid Ciężarkiewicz
 // See LICENSE file for more information
 
+use ai::{Actor,AIActor,Order,AttackAt};
 use creature::{Creature};
 use creature::{Race,Human,Scout,Grunt,Heavy};
+use creature::Wildlife;
 use hex2d;
 use hex2d::{Point,Position,Direction};
 use hex2d::{Forward,Backward};
-use map::{Tile,Map};
-use map::{Wall,Floor,GlassWall,Sand};
+use map::{Tile,Map,TileType};
+use map::{Wall,Floor,GlassWall,Sand,Water,DeepWater,Lava,Torch};
+use light;
+use map;
+use memory;
+use noise;
+use noise::{Noise,DEATH_LOUDNESS};
+use progress::Perk;
+use script::{Script,ScriptActor};
 use std::rand;
-use std::rand::Rng;
+use std::rand::{Rng,SeedableRng};
 use std::cell::{RefCell};
 use std::rc::{Rc};
 use std::vec::Vec;
 use std::slice::Items;
 use std::collections::{RingBuf};
+use std::mem;
 
 pub type CreatureRef = Rc<RefCell<Creature>>;
 pub type Creatures = Vec<CreatureRef>;
 
+/// Events kept until someone takes them
+static MAX_EVENTS : uint = 1000;
+
 pub struct GameState {
     pub map : Box<Map>,
     pub player : Option<CreatureRef>,
-    rng : rand::TaskRng,
+    rng : rand::XorShiftRng,
     creatures: Creatures,
     pending_tick: RingBuf<CreatureRef>,
+    lights : light::Lights,
+    noises: Vec<Noise>,
     tick : uint,
+    /// Script controlling the monsters instead of the built-in AI
+    script : Option<Rc<RefCell<Script>>>,
+    events : RingBuf<Event>,
+    /// Hostile creatures the player saw during the last check
+    spotted : Vec<uint>,
 }
 
 #[deriving(Show)]
@@ -37,25 +57,81 @@ pub enum Action {
     Wait
 }
 
+/// Creature taking part in an `Event`
+pub struct Subject {
+    pub race : Race,
+    pub is_player : bool,
+}
+
+impl Subject {
+    fn of(cr : &Creature) -> Subject {
+        Subject {
+            race: cr.race(),
+            is_player: cr.is_player(),
+        }
+    }
+}
+
+/// Something that happened in the game
+pub enum Event {
+    /// `Hit(attacker, target, damage)`
+    Hit(Subject, Subject, uint),
+    /// `Killed(attacker, target)`
+    Killed(Subject, Subject),
+    /// Damage from terrain or effects
+    Suffered(Subject, uint),
+    /// Death from terrain or effects
+    Died(Subject),
+    /// Tried to walk into a tile it can't pass
+    Blocked(Subject, TileType),
+    Smashed(Subject, TileType),
+    /// The player noticed a hostile creature
+    Spotted(Race),
+    /// The player reached a new level, and can pick a perk if `true`
+    LevelUp(uint, bool),
+}
+
 impl GameState {
     pub fn new() -> GameState {
+        GameState::with_rng(rand::task_rng().gen())
+    }
+
+    /// Game with the map and spawns determined by `seed`
+    pub fn with_seed(seed : u32) -> GameState {
+        GameState::with_rng(SeedableRng::from_seed([0x193a6754, 0xa8a7d469, 0x97830e05, seed]))
+    }
+
+    fn with_rng(rng : rand::XorShiftRng) -> GameState {
         let map = box hex2d::Map::new(100, 100, Tile {
             tiletype: Floor,
             creature: None,
+            damage: 0,
+            light: 0,
         }
         );
         GameState {
             player: None,
-            rng: rand::task_rng(),
+            rng: rng,
             map: map,
             creatures: Vec::new(),
             pending_tick: RingBuf::new(),
+            lights: light::Lights::new(),
+            noises: Vec::new(),
             tick: 0,
+            script: None,
+            events: RingBuf::new(),
+            spotted: Vec::new(),
         }
     }
 
+    /// Control monsters spawned from now on with the script at `path`
+    pub fn use_script(&mut self, path : Path) {
+        self.script = Some(Script::new(path));
+    }
+
     fn spawn(&mut self, cr : Creature) -> Option<CreatureRef>  {
-        if !self.map.at(*cr.p()).is_passable() {
+        let tile = self.map.at(*cr.p());
+        if !tile.is_passable_for(cr.race()) || tile.is_harmful() || tile.creature.is_some() {
             None
         } else {
             let p = *cr.p();
@@ -70,7 +146,11 @@ impl GameState {
     fn spawn_random(&mut self, player : bool, race : Race) -> Rc<RefCell<Creature>> {
         loop {
             let pos = self.map.wrap(self.rng.gen::<Position>());
-            let cr = Creature::new(&*self.map, pos, player, race);
+            let actor : Box<Actor+'static> = match self.script {
+                Some(ref script) if !player => box ScriptActor::new(script.clone(), &mut self.rng),
+                _ => box AIActor::for_race(race, &mut self.rng),
+            };
+            let cr = Creature::with_actor(&*self.map, pos, player, race, actor);
             match self.spawn(cr) {
                 Some(cr) => return cr,
                 None => {}
@@ -85,59 +165,168 @@ impl GameState {
             cr.pos_set(&*self.map, pos);
             return;
         }
-        if !self.map.at(pos_p).is_passable() {
+        if !self.map.at(pos_p).is_passable_type_for(cr.race()) {
+            let tiletype = self.map.at(pos_p).tiletype;
+            self.push_event(Blocked(Subject::of(cr), tiletype));
             return;
         }
 
-        match self.map.at(pos_p).creature {
-            Some(_) => { },
+        match self.map.at(pos_p).creature.clone() {
+            Some(other_ref) => self.swap_if_possible(cr, pos, other_ref),
             None => {
                 self.map.mut_at(pos_p).creature = self.map.at(cr_p).creature.clone();
                 self.map.mut_at(cr_p).creature = None;
+                if cr.light() > 0 {
+                    self.update_light(&[(pos_p, cr.light())]);
+                }
                 cr.pos_set(&*self.map, pos);
             }
         }
     }
 
+    /// Let `cr` switch places with an ally standing at `pos`
+    ///
+    /// Nobody pushes the player around, though.
+    fn swap_if_possible(&mut self, cr : &mut Creature, pos : Position, other_ref : CreatureRef) {
+        let cr_p = *cr.p();
+        let mut other = match other_ref.try_borrow_mut() {
+            Some(other) => other,
+            None => return,
+        };
+
+        if !other.is_alive() || other.is_player() || other.faction() != cr.faction()
+            || !self.map.at(cr_p).is_passable_type_for(other.race()) {
+            return;
+        }
+
+        self.map.mut_at(pos.p).creature = self.map.at(cr_p).creature.clone();
+        self.map.mut_at(cr_p).creature = Some(other_ref.clone());
+
+        let other_pos = Position{ p: cr_p, dir: other.pos().dir };
+        other.pos_set(&*self.map, other_pos);
+        cr.pos_set(&*self.map, pos);
+
+        if cr.light() > 0 || other.light() > 0 {
+            self.update_light(&[(pos.p, cr.light()), (cr_p, other.light())]);
+        }
+    }
+
+    /// Put a companion of the player next to it
+    pub fn spawn_companion(&mut self) -> Option<CreatureRef> {
+        let pos = match self.player {
+            Some(ref pl) => *pl.borrow().pos(),
+            None => return None,
+        };
+
+        for &n in pos.p.neighbors().iter() {
+            let p = self.map.wrap(n);
+            let cr = Creature::with_actor(&*self.map, Position{ p: p, dir: pos.dir }, false, Human,
+                                          box AIActor::companion(&mut self.rng));
+            match self.spawn(cr) {
+                Some(cr) => return Some(cr),
+                None => {}
+            }
+        }
+
+        None
+    }
+
+    /// Take `perk` for the player's level up
+    pub fn choose_perk(&self, perk : Perk) -> bool {
+        match self.player {
+            Some(ref pl) => pl.borrow_mut().choose_perk(perk),
+            None => false,
+        }
+    }
+
+    /// Give an order to everyone on the player's side
+    pub fn order_companions(&self, order : Order) {
+        let faction = match self.player {
+            Some(ref pl) => pl.borrow().faction(),
+            None => return,
+        };
+
+        let order = match order {
+            AttackAt(p) => AttackAt(self.map.wrap(p)),
+            order => order,
+        };
+
+        for cr in self.creatures.iter() {
+            match cr.try_borrow_mut() {
+                Some(mut cr) => {
+                    if cr.is_alive() && !cr.is_player() && cr.faction() == faction {
+                        cr.ordered(order);
+                    }
+                },
+                None => {}
+            }
+        }
+    }
+
+    /// Have companions of `leader` help it fight the creature at `p`
+    fn assist(&self, leader : &Creature, p : Point) {
+        for cr in self.creatures.iter() {
+            match cr.try_borrow_mut() {
+                Some(mut cr) => {
+                    if cr.is_alive() && cr.faction() == leader.faction() {
+                        cr.leader_attacked(p);
+                    }
+                },
+                None => {}
+            }
+        }
+    }
+
+    /// Light the map again where lights changed
+    ///
+    /// Creatures currently performing an action are already borrowed,
+    /// so their positions and lights have to be passed in `borrowed`.
+    fn update_light(&mut self, borrowed : &[(Point, uint)]) {
+        self.lights.update(&mut *self.map, &self.creatures, borrowed);
+    }
+
     pub fn creatures_iter(&self) -> Items<CreatureRef> {
         self.creatures.iter()
     }
 
+    /// Number of ticks since the game started
+    pub fn current_tick(&self) -> uint {
+        self.tick
+    }
+
+    /// Advance the game
+    ///
+    /// Returns `true` when stopped to wait for an actor that has not
+    /// decided what to do yet, `false` after a whole tick.
     pub fn tick(&mut self) -> bool {
         let mut creatures = self.creatures.clone();
 
         if self.pending_tick.is_empty() {
+            self.tick += 1;
             for creature in creatures.iter_mut() {
+                creature.borrow_mut().set_now(self.tick);
                 self.pending_tick.push_back(creature.clone());
             }
-            self.tick += 1;
+            self.apply_damage_over_time();
+            self.spread_noises();
+            self.update_light(&[]);
         }
 
         loop {
             let cr = self.pending_tick.pop_front();
             match cr {
                 None => break,
-                Some(ref cr) => {
-                    if match cr.borrow_mut() {
-                        cr => {
-                            if cr.is_alive() && cr.needs_action() && cr.is_player() {
-                                true
-                            } else {
-                                false
-                            }
-                        }
-                    } {
-                        self.pending_tick.push_front(cr.clone());
-                        return true;
-                    };
-
-                    match cr.borrow_mut()  {
+                Some(ref cr_ref) => {
+                    match cr_ref.borrow_mut()  {
                         mut cr => {
                             if cr.is_alive() {
                                 if cr.needs_action() {
                                     cr.update_los(&*self.map);
-                                    assert!(!cr.is_player());
-                                    cr.update_action(&*self.map);
+                                    if !cr.update_action(&*self.map) {
+                                        self.pending_tick.push_front(cr_ref.clone());
+                                        return true;
+                                    }
+                                    self.share_sighting(&mut *cr);
                                 }
                                 let action = cr.tick();
                                 match action {
@@ -150,6 +339,8 @@ impl GameState {
                             }
                         },
                     }
+
+                    self.spread_noises();
                 }
             }
         }
@@ -157,10 +348,135 @@ impl GameState {
         false
     }
 
+    /// Events that happened since the last call, oldest first
+    pub fn take_events(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        loop {
+            match self.events.pop_front() {
+                Some(event) => events.push(event),
+                None => return events,
+            }
+        }
+    }
+
+    fn push_event(&mut self, event : Event) {
+        if self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Report hostile creatures that the player sees and didn't see before
+    pub fn check_spotted(&mut self) {
+        let player = match self.player {
+            Some(ref pl) => pl.clone(),
+            None => return,
+        };
+        let player = player.borrow();
+
+        let mut seen = Vec::new();
+        let mut new = Vec::new();
+
+        for other_ref in self.creatures.iter() {
+            match other_ref.try_borrow() {
+                Some(other) => {
+                    if other.is_alive() && player.is_hostile_to(&*other)
+                        && player.sees(*other.p()) {
+                        let id = memory::creature_id(other_ref);
+                        if !self.spotted.contains(&id) {
+                            new.push(other.race());
+                        }
+                        seen.push(id);
+                    }
+                },
+                None => {}
+            }
+        }
+
+        self.spotted = seen;
+        for race in new.into_iter() {
+            self.push_event(Spotted(race));
+        }
+    }
+
+    /// Tell allies close to `cr` what it has seen
+    fn share_sighting(&self, cr : &mut Creature) {
+        let (pos, distance) = match cr.take_sighting() {
+            Some(sighting) => sighting,
+            None => return,
+        };
+
+        for other in self.creatures.iter() {
+            match other.try_borrow_mut() {
+                Some(mut other) => {
+                    if other.is_alive() && other.faction() == cr.faction()
+                        && map::distance(*cr.p(), *other.p()) <= distance {
+                        other.told_about(pos);
+                    }
+                },
+                None => {}
+            }
+        }
+    }
+
+    /// Hurt creatures standing on harmful terrain and count down
+    /// their effects
+    fn apply_damage_over_time(&mut self) {
+        let mut events = Vec::new();
+
+        for cr in self.creatures.iter() {
+            let mut cr = cr.borrow_mut();
+            if !cr.is_alive() {
+                continue;
+            }
+
+            let p = *cr.p();
+            let damage = self.map.at(p).tiletype.damage() + cr.tick_effects();
+            if damage > 0 {
+                cr.hurt(damage);
+                events.push(Suffered(Subject::of(&*cr), damage));
+                if !cr.is_alive() {
+                    self.map.mut_at(p).creature = None;
+                    self.noises.push(Noise::new(p, DEATH_LOUDNESS, cr.faction()));
+                    events.push(Died(Subject::of(&*cr)));
+                }
+            }
+        }
+
+        for event in events.into_iter() {
+            self.push_event(event);
+        }
+    }
+
+    /// Let everyone hear the noises made since the last call
+    fn spread_noises(&mut self) {
+        let noises = mem::replace(&mut self.noises, Vec::new());
+        let map = &*self.map;
+        let rng = &mut self.rng;
+
+        for noise in noises.iter() {
+            noise::propagate(map, noise, &mut *rng, |cr, heard| {
+                match cr.try_borrow_mut() {
+                    Some(mut cr) => {
+                        if cr.is_alive() {
+                            cr.hear(map, &heard);
+                        }
+                    },
+                    None => {}
+                }
+            });
+        }
+    }
+
     pub fn perform_action(&mut self, cr : &mut Creature, action : Action) {
         let old_pos = *cr.pos();
         cr.pos_prev_set(&*self.map, old_pos);
 
+        let loudness = noise::action_loudness(action);
+        if loudness > 0 {
+            self.noises.push(Noise::new(old_pos.p, loudness, cr.faction()));
+        }
+
         match action {
             Turn(Forward)|Turn(Backward) => panic!("Illegal move"),
             Move(dir)|Run(dir) => {
@@ -176,13 +492,39 @@ impl GameState {
                 let target = self.map.mut_at(target_p).creature.as_ref().
                     map(|cr| cr.clone());
                 if target.is_some() {
-                    let target = target.unwrap();
-                    let target = &mut *target.borrow_mut();
-                    target.attacked_by(cr);
-                    cr.attacked(target);
+                    let alive = {
+                        let target = target.unwrap();
+                        let target = &mut *target.borrow_mut();
+                        let damage = target.attacked_by(cr);
+                        let level = cr.progress().map(|progress| progress.level);
+                        cr.attacked(target, damage);
+                        self.push_event(Hit(Subject::of(cr), Subject::of(target), damage));
+
+                        if !target.is_alive() {
+                            self.map.mut_at(target_p).creature = None;
+                            self.noises.push(Noise::new(target_p, DEATH_LOUDNESS, target.faction()));
+                            self.push_event(Killed(Subject::of(cr), Subject::of(target)));
+                        }
 
-                    if !target.is_alive() {
-                        self.map.mut_at(target_p).creature = None;
+                        match cr.progress() {
+                            Some(progress) if Some(progress.level) != level => {
+                                self.push_event(LevelUp(progress.level, progress.perks_pending > 0));
+                            },
+                            _ => {}
+                        }
+                        target.is_alive()
+                    };
+
+                    if alive && cr.is_player() {
+                        self.assist(cr, target_p);
+                    }
+                } else if self.map.at(target_p).is_destructible() {
+                    let tiletype = self.map.at(target_p).tiletype;
+                    cr.attacked_tile(tiletype);
+                    if self.map.mut_at(target_p).smash(cr.damage()) {
+                        self.push_event(Smashed(Subject::of(cr), tiletype));
+                        self.lights.tile_changed(&mut *self.map, target_p, tiletype.light());
+                        self.tile_changed(cr, target_p);
                     }
                 }
             },
@@ -190,6 +532,25 @@ impl GameState {
         }
     }
 
+    /// Tile at `p` changed its opaqueness or passability
+    ///
+    /// Refresh LoS of everyone that could see it. `cr` is the creature
+    /// currently performing an action and is already borrowed.
+    fn tile_changed(&self, cr : &mut Creature, p : Point) {
+        cr.refresh_los(&*self.map);
+
+        for other in self.creatures.iter() {
+            match other.try_borrow_mut() {
+                Some(mut other) => {
+                    if other.is_alive() && other.sees(p) {
+                        other.refresh_los(&*self.map);
+                    }
+                },
+                None => {}
+            }
+        }
+    }
+
     pub fn randomize_map(&mut self) {
         let height = self.map.height() as int;
         let width = self.map.width() as int;
@@ -199,9 +560,12 @@ impl GameState {
             let p = self.rng.gen::<Point>();
             let p = self.map.wrap(p);
 
-            let t = match self.rng.gen_range(0u, 20) {
-                0 => GlassWall,
-                1 => Sand,
+            let t = match self.rng.gen_range(0u, 40) {
+                0|1 => GlassWall,
+                2|3 => Sand,
+                4|5 => Water,
+                6 => DeepWater,
+                7 => Lava,
                 _ => Wall
             };
 
@@ -212,6 +576,15 @@ impl GameState {
             }
         }
 
+        for _ in range(0, area / 100) {
+            let p = self.rng.gen::<Point>();
+            let p = self.map.wrap(p);
+
+            if self.map.at(p).tiletype == Wall {
+                self.map.mut_at(p).tiletype = Torch;
+            }
+        }
+
         for x in range(0i, width) {
             let p = Point::new(x, 0);
             self.map.mut_at(p).tiletype = Wall;
@@ -239,14 +612,32 @@ impl GameState {
             self.spawn_random(false, Heavy);
         }
 
+        /* Some Scouts run wild, away from the packs */
+        for _ in range(0, area / 800) {
+            self.spawn_random(false, Scout).borrow_mut().set_faction(Wildlife);
+        }
+
         let p = self.spawn_random(true, Human);
 
         self.player = Some(p);
+
+        self.lights.reset(&mut *self.map, &self.creatures);
+    }
+
+    /// Number of enemies `cr` can currently see
+    pub fn visible_enemies(&self, cr : &Creature) -> uint {
+        self.creatures.iter().filter(|other| {
+            match other.try_borrow() {
+                Some(other) => other.is_alive() && cr.is_hostile_to(&*other)
+                    && cr.sees(*other.p()),
+                None => false,
+            }
+        }).count()
     }
 
     pub fn update_player_los(&self) {
         match self.player {
-            Some(ref pl) => pl.borrow_mut().update_los(&*self.map),
+            Some(ref pl) => pl.borrow_mut().refresh_los(&*self.map),
             None => {}
         }
     }
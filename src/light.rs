@@ -0,0 +1,339 @@
+// Copyright 2014 Dawid Ciężarkiewicz
+// See LICENSE file for more information
+
+//! Light spread over the map by torches, lava and glowing creatures
+//!
+//! Lighting the whole map is costly, so after the first time only the
+//! tiles that light from a changed source could reach are lit again.
+//! Light coming into them from the rest of the map is taken from the
+//! tiles around, which keep their light.
+
+use game::Creatures;
+use hex2d::{Point};
+use map::{Map,PointSet};
+use std::cmp::max;
+use std::collections::{RingBuf};
+
+/// Light level of tiles not lit by any source
+pub static AMBIENT_LIGHT : uint = 0;
+
+/// Opaqueness at which a tile stops light from spreading further
+static LIGHT_BLOCKING : uint = 1000;
+
+/// Light sources currently spread over the map
+pub struct Lights {
+    /// Positions and lights of the creatures, as last spread
+    creatures : Vec<(Point, uint)>,
+}
+
+impl Lights {
+    pub fn new() -> Lights {
+        Lights {
+            creatures: Vec::new(),
+        }
+    }
+
+    /// Light the whole map from scratch
+    pub fn reset(&mut self, map : &mut Map, creatures : &Creatures) {
+        let width = map.width() as int;
+        let height = map.height() as int;
+
+        for y in range(0i, height) {
+            for x in range(0i, width) {
+                map.mut_at(Point::new(x, y)).light = AMBIENT_LIGHT;
+            }
+        }
+
+        for y in range(0i, height) {
+            for x in range(0i, width) {
+                let p = Point::new(x, y);
+                let light = map.at(p).tiletype.light();
+                if light > 0 {
+                    spread(map, p, light);
+                }
+            }
+        }
+
+        self.creatures = creature_lights(creatures, &[]);
+        for &(p, light) in self.creatures.iter() {
+            spread(map, p, light);
+        }
+    }
+
+    /// Light again around creatures' lights that moved, appeared or went
+    /// out since the last update
+    ///
+    /// Creatures that are currently borrowed are skipped and the caller
+    /// has to pass their positions and lights in `borrowed`.
+    pub fn update(&mut self, map : &mut Map, creatures : &Creatures,
+                  borrowed : &[(Point, uint)]) {
+        let lights = creature_lights(creatures, borrowed);
+
+        let mut changed = Vec::new();
+        for light in self.creatures.iter() {
+            if !lights.contains(light) {
+                changed.push(*light);
+            }
+        }
+        for light in lights.iter() {
+            if !self.creatures.contains(light) {
+                changed.push(*light);
+            }
+        }
+
+        self.creatures = lights;
+        if !changed.is_empty() {
+            self.relight(map, changed.as_slice());
+        }
+    }
+
+    /// Light again around `p` after its tile changed
+    ///
+    /// `old_light` is what the tile emitted before the change.
+    pub fn tile_changed(&mut self, map : &mut Map, p : Point, old_light : uint) {
+        let p = map.wrap(p);
+
+        /* Light passing through can't be brighter than around */
+        let mut light = max(old_light, map.at(p).tiletype.light());
+        for &n in p.neighbors().iter() {
+            light = max(light, map.at(map.wrap(n)).light);
+        }
+
+        self.relight(map, &[(p, light)]);
+    }
+
+    /// Light emitted at `p`
+    fn source_at(&self, map : &Map, p : Point) -> uint {
+        self.creatures.iter().filter(|&&(cr_p, _)| cr_p == p)
+            .fold(map.at(p).tiletype.light(), |light, &(_, cr_light)| max(light, cr_light))
+    }
+
+    /// Light again the tiles that `changed` sources could reach
+    fn relight(&self, map : &mut Map, changed : &[(Point, uint)]) {
+        let mut area = PointSet::new(&*map);
+        for &(p, light) in changed.iter() {
+            reach(map, p, light, &mut area);
+        }
+
+        for &p in area.iter() {
+            map.mut_at(p).light = AMBIENT_LIGHT;
+        }
+
+        let mut queue = RingBuf::new();
+        for &p in area.iter() {
+            let light = self.source_at(map, p);
+            if light > 0 {
+                light_up(map, &mut queue, p, light);
+                spread_from(map, &mut queue, p, light);
+            }
+
+            for &n in p.neighbors().iter() {
+                let n = map.wrap(n);
+                if area.contains(n) {
+                    continue;
+                }
+
+                let passing = if map.at(n).opaqueness() < LIGHT_BLOCKING {
+                    map.at(n).light
+                } else {
+                    0
+                };
+                let light = max(self.source_at(map, n), passing);
+                if light > 1 {
+                    light_up(map, &mut queue, p, light - 1);
+                }
+            }
+        }
+
+        flood(map, &mut queue);
+    }
+}
+
+/// Lights of all the living creatures, with their positions
+fn creature_lights(creatures : &Creatures, borrowed : &[(Point, uint)]) -> Vec<(Point, uint)> {
+    let mut lights : Vec<(Point, uint)> = borrowed.iter()
+        .filter(|&&(_, light)| light > 0).map(|&light| light).collect();
+
+    for cr in creatures.iter() {
+        match cr.try_borrow() {
+            Some(cr) => {
+                if cr.is_alive() && cr.light() > 0 {
+                    lights.push((*cr.p(), cr.light()));
+                }
+            },
+            None => {}
+        }
+    }
+
+    lights
+}
+
+/// Add tiles that light of a source at `p` could reach to `area`
+///
+/// Walls are ignored, they only make the way longer.
+fn reach(map : &Map, p : Point, light : uint, area : &mut PointSet) {
+    let mut seen = PointSet::new(map);
+    let mut queue = RingBuf::new();
+    let p = map.wrap(p);
+
+    seen.insert(p);
+    queue.push_back((p, light));
+
+    loop {
+        let (p, light) = match queue.pop_front() {
+            Some(next) => next,
+            None => break,
+        };
+
+        area.insert(p);
+        if light <= 1 {
+            continue;
+        }
+
+        for &n in p.neighbors().iter() {
+            let n = map.wrap(n);
+            if seen.insert(n) {
+                queue.push_back((n, light - 1));
+            }
+        }
+    }
+}
+
+/// Spread light of a source at `p` over the map
+///
+/// Light gets dimmer with every tile and does not pass through walls.
+fn spread(map : &mut Map, p : Point, light : uint) {
+    let mut queue = RingBuf::new();
+    let p = map.wrap(p);
+
+    light_up(map, &mut queue, p, light);
+    /* Source itself can be a wall (eg. a torch) */
+    spread_from(map, &mut queue, p, light);
+
+    flood(map, &mut queue);
+}
+
+/// Keep spreading light from the tiles in `queue`
+fn flood(map : &mut Map, queue : &mut RingBuf<(Point, uint)>) {
+    loop {
+        let (p, light) = match queue.pop_front() {
+            Some(next) => next,
+            None => break,
+        };
+
+        if map.at(p).opaqueness() >= LIGHT_BLOCKING {
+            continue;
+        }
+
+        spread_from(map, queue, p, light);
+    }
+}
+
+fn spread_from(map : &mut Map, queue : &mut RingBuf<(Point, uint)>, p : Point, light : uint) {
+    if light <= 1 {
+        return;
+    }
+
+    for &n in p.neighbors().iter() {
+        let n = map.wrap(n);
+        light_up(map, queue, n, light - 1);
+    }
+}
+
+fn light_up(map : &mut Map, queue : &mut RingBuf<(Point, uint)>, p : Point, light : uint) {
+    if map.at(p).light < light {
+        map.mut_at(p).light = light;
+        queue.push_back((p, light));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex2d;
+    use hex2d::Point;
+    use map::{Map,Tile,TileType,Floor,Wall,Torch,Debris};
+    use super::Lights;
+
+    fn floor_map() -> Map {
+        hex2d::Map::new(30, 30, Tile {
+            tiletype: Floor,
+            creature: None,
+            damage: 0,
+            light: 0,
+        })
+    }
+
+    fn test_map() -> Map {
+        let mut map = floor_map();
+
+        for y in range(3i, 12) {
+            set(&mut map, Point::new(8, y), Wall);
+        }
+        set(&mut map, Point::new(5, 5), Torch);
+        set(&mut map, Point::new(12, 7), Torch);
+
+        map
+    }
+
+    /// Map with the same terrain, lit from scratch with creatures' `lights`
+    fn lit_from_scratch(terrain : &Map, lights : &[(Point, uint)]) -> Map {
+        let mut map = floor_map();
+        for y in range(0i, map.height() as int) {
+            for x in range(0i, map.width() as int) {
+                let p = Point::new(x, y);
+                set(&mut map, p, terrain.at(p).tiletype);
+            }
+        }
+
+        let mut from_scratch = Lights::new();
+        from_scratch.reset(&mut map, &Vec::new());
+        from_scratch.update(&mut map, &Vec::new(), lights);
+        map
+    }
+
+    fn assert_same_light(a : &Map, b : &Map) {
+        for y in range(0i, a.height() as int) {
+            for x in range(0i, a.width() as int) {
+                let p = Point::new(x, y);
+                assert_eq!(a.at(p).light, b.at(p).light);
+            }
+        }
+    }
+
+    fn set(map : &mut Map, p : Point, tiletype : TileType) {
+        map.mut_at(p).tiletype = tiletype;
+    }
+
+    #[test]
+    fn moving_light() {
+        let mut map = test_map();
+        let mut lights = Lights::new();
+        lights.reset(&mut map, &Vec::new());
+
+        for x in range(2i, 14) {
+            let lantern = [(Point::new(x, 7), 5u)];
+            lights.update(&mut map, &Vec::new(), &lantern);
+            assert_same_light(&map, &lit_from_scratch(&map, &lantern));
+        }
+
+        lights.update(&mut map, &Vec::new(), &[]);
+        assert_same_light(&map, &lit_from_scratch(&map, &[]));
+    }
+
+    #[test]
+    fn smashed_tiles() {
+        let mut map = test_map();
+        let mut lights = Lights::new();
+        lights.reset(&mut map, &Vec::new());
+
+        let wall = Point::new(8, 6);
+        set(&mut map, wall, Debris);
+        lights.tile_changed(&mut map, wall, 0);
+        assert_same_light(&map, &lit_from_scratch(&map, &[]));
+
+        let torch = Point::new(12, 7);
+        set(&mut map, torch, Debris);
+        lights.tile_changed(&mut map, torch, Torch.light());
+        assert_same_light(&map, &lit_from_scratch(&map, &[]));
+    }
+}
@@ -23,13 +23,27 @@ extern crate hex2d;
 extern crate genmesh;
 extern crate "obj-rs" as obj;
 #[phase(plugin, link)] extern crate log;
+extern crate serialize;
 extern crate input;
+extern crate lua;
 
 mod ui;
 mod game;
 mod creature;
 mod ai;
 mod map;
+mod light;
+mod fov;
+mod memory;
+mod noise;
+mod effect;
+mod progress;
+pub mod path;
+mod script;
+mod tournament;
+
+/// Where the player's progress is kept between games
+static SAVE_FILE : &'static str = "rustyhex.save";
 
 #[start]
 fn start(argc: int, argv: *const *const u8) -> int {
@@ -38,11 +52,54 @@ fn start(argc: int, argv: *const *const u8) -> int {
 
 #[main]
 pub fn main() {
+    let args = std::os::args();
+
+    match args.as_slice() {
+        [_, ref opt, ref games, ref a, ref b] if opt.as_slice() == "--tournament" => {
+            match from_str::<uint>(games.as_slice()) {
+                Some(games) => tournament::run(games, a.as_slice(), b.as_slice()),
+                None => println!("Number of games expected, got `{}`", games),
+            }
+            return;
+        },
+        _ => {}
+    }
+
     let (mut ui, window) = ui::piston::PistonUI::new();
 
     let mut game = game::GameState::new();
 
+    match args.as_slice() {
+        [_, ref opt, ref path] if opt.as_slice() == "--script" => {
+            game.use_script(Path::new(path.as_slice()));
+        },
+        _ => {}
+    }
+
     game.randomize_map();
 
+    let save = Path::new(SAVE_FILE);
+    match (progress::Progress::load(&save), game.player.as_ref()) {
+        (Some(progress), Some(pl)) => pl.borrow_mut().set_progress(progress),
+        _ => {}
+    }
+
     ui.run(window, &mut game);
+
+    /* Dead characters don't come back */
+    match game.player {
+        Some(ref pl) if !pl.borrow().is_alive() => match std::io::fs::unlink(&save) {
+            Ok(()) => {},
+            Err(ref err) if err.kind == std::io::FileNotFound => {},
+            Err(err) => error!("save {}: {}", save.display(), err),
+        },
+        Some(ref pl) => match pl.borrow().progress() {
+            Some(progress) => match progress.save(&save) {
+                Ok(()) => {},
+                Err(err) => error!("save {}: {}", save.display(), err),
+            },
+            None => {}
+        },
+        None => {}
+    }
 }
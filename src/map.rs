@@ -4,7 +4,11 @@
 use hex2d;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::cmp::max;
+use std::slice::Items;
 use creature::{Creature};
+use creature::Race;
+use effect::{Effect,Slowed,Bleeding};
 
 
 #[deriving(Eq)]
@@ -15,18 +19,25 @@ pub enum TileType {
     GlassWall,
     Wall,
     Sand,
+    Water,
+    DeepWater,
+    Lava,
+    Debris,
+    Torch,
 }
 
 #[deriving(Clone)]
 pub struct Tile {
     pub tiletype : TileType,
     pub creature : Option<Rc<RefCell<Creature>>>,
+    pub damage : uint,
+    pub light : uint,
 }
 
 impl Tile {
     pub fn opaqueness(&self) -> uint {
         let o = match self.tiletype {
-            Wall => 1000000,
+            Wall|Torch => 1000000,
             GlassWall => 3,
             _ => 1
         };
@@ -34,25 +45,212 @@ impl Tile {
     }
 
     pub fn is_passable_type(&self) -> bool {
-        match self.tiletype {
-            Wall|GlassWall => false,
-            _ => true
-        }
+        self.tiletype.is_passable()
     }
 
     pub fn is_passable(&self) -> bool {
         self.is_passable_type() && self.creature.is_none()
     }
 
+    /// Like `is_passable_type`, but heavy races sink in deep water
+    pub fn is_passable_type_for(&self, race : Race) -> bool {
+        self.tiletype.is_passable_for(race)
+    }
+
+    pub fn is_passable_for(&self, race : Race) -> bool {
+        self.is_passable_type_for(race) && self.creature.is_none()
+    }
+
+    /// Does standing on this tile hurt
+    pub fn is_harmful(&self) -> bool {
+        self.tiletype.is_harmful()
+    }
+
+    pub fn is_destructible(&self) -> bool {
+        self.tiletype.durability() > 0
+    }
+
+    /// Smash the tile
+    ///
+    /// Returns `true` if it got destroyed and turned into debris.
+    pub fn smash(&mut self, damage : uint) -> bool {
+        if !self.is_destructible() {
+            return false;
+        }
+
+        self.damage += damage;
+        if self.damage >= self.tiletype.durability() {
+            self.tiletype = Debris;
+            self.damage = 0;
+            true
+        } else {
+            false
+        }
+    }
 }
 
 impl TileType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Wall => "wall",
+            &GlassWall => "glass wall",
+            &Floor => "floor",
+            &Sand => "sand",
+            &Water => "water",
+            &DeepWater => "deep water",
+            &Lava => "lava",
+            &Debris => "debris",
+            &Torch => "torch",
+        }
+    }
+
+    pub fn is_passable(&self) -> bool {
+        match self {
+            &Wall|&GlassWall|&Torch => false,
+            _ => true
+        }
+    }
+
+    /// Like `is_passable`, but heavy races sink in deep water
+    pub fn is_passable_for(&self, race : Race) -> bool {
+        match self {
+            &DeepWater => !race.is_heavy(),
+            _ => self.is_passable(),
+        }
+    }
+
+    pub fn is_harmful(&self) -> bool {
+        self.damage() > 0
+    }
+
     pub fn move_delay(&self) -> uint {
         match self {
             &Sand => 1,
+            &Water => 1,
+            &DeepWater => 2,
             _ => 0
         }
     }
+
+    pub fn allows_running(&self) -> bool {
+        match self {
+            &Water|&DeepWater => false,
+            _ => true
+        }
+    }
+
+    /// Damage a tile can take before being destroyed, 0 if indestructible
+    pub fn durability(&self) -> uint {
+        match self {
+            &GlassWall => 4,
+            _ => 0
+        }
+    }
+
+    /// Light emitted by the tile
+    pub fn light(&self) -> uint {
+        match self {
+            &Torch => 6,
+            &Lava => 3,
+            _ => 0
+        }
+    }
+
+    /// How much noise loses when passing through the tile
+    pub fn noise_damping(&self) -> uint {
+        match self {
+            &Wall|&Torch => 4,
+            &GlassWall => 2,
+            _ => 1
+        }
+    }
+
+    /// Damage dealt every tick to a creature standing on the tile
+    pub fn damage(&self) -> uint {
+        match self {
+            &Lava => 1,
+            _ => 0
+        }
+    }
+
+    /// Effect put on a creature entering the tile, and for how long
+    pub fn effect(&self) -> Option<(Effect, uint)> {
+        match self {
+            &DeepWater => Some((Slowed, 6)),
+            &Lava => Some((Bleeding, 10)),
+            _ => None
+        }
+    }
 }
 
 pub type Map = hex2d::Map<Tile>;
+
+/// Distance between two points, in tiles
+pub fn distance(a : hex2d::Point, b : hex2d::Point) -> uint {
+    let dx = a.x - b.x;
+    let dy = a.y - b.y;
+    let dz = - dx - dy;
+
+    let abs = |v : int| if v < 0 { -v } else { v };
+
+    max(abs(dx), max(abs(dy), abs(dz))) as uint
+}
+
+/// Set of points of a map
+///
+/// Bitset for quick lookups, plus a list of members so that iterating
+/// and clearing cost as much as the number of points in the set, not
+/// the area of the map.
+pub struct PointSet {
+    width : uint,
+    bits : Vec<u32>,
+    points : Vec<hex2d::Point>,
+}
+
+impl PointSet {
+    pub fn new<T>(map : &hex2d::Map<T>) -> PointSet {
+        let area = map.width() * map.height();
+        PointSet {
+            width: map.width(),
+            bits: Vec::from_elem((area + 31) / 32, 0u32),
+            points: Vec::new(),
+        }
+    }
+
+    fn index(&self, p : hex2d::Point) -> (uint, u32) {
+        let i = p.y as uint * self.width + p.x as uint;
+        (i / 32, 1u32 << (i % 32))
+    }
+
+    pub fn contains(&self, p : hex2d::Point) -> bool {
+        let (word, bit) = self.index(p);
+        self.bits[word] & bit != 0
+    }
+
+    /// Add a point, returns `false` if it was already there
+    pub fn insert(&mut self, p : hex2d::Point) -> bool {
+        let (word, bit) = self.index(p);
+        if self.bits[word] & bit != 0 {
+            return false;
+        }
+        self.bits.as_mut_slice()[word] |= bit;
+        self.points.push(p);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        for &p in self.points.iter() {
+            let (word, bit) = self.index(p);
+            self.bits.as_mut_slice()[word] &= !bit;
+        }
+        self.points.clear();
+    }
+
+    pub fn len(&self) -> uint {
+        self.points.len()
+    }
+
+    pub fn iter(&self) -> Items<hex2d::Point> {
+        self.points.iter()
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright 2014 Dawid Ciężarkiewicz
+// See LICENSE file for more information
+
+use creature::{Creature,Race};
+use hex2d;
+use hex2d::{Point,Position};
+use map::{Map,TileType};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::slice::Items;
+
+/// What was last seen on a tile
+#[deriving(Clone)]
+pub struct TileMemory {
+    pub tiletype : TileType,
+    pub tick : uint,
+}
+
+/// Where and how a creature was last seen
+#[deriving(Clone)]
+pub struct CreatureMemory {
+    pub id : uint,
+    pub race : Race,
+    pub is_player : bool,
+    pub pos : Position,
+    pub tick : uint,
+}
+
+/// Identity of a creature, stable for the whole game
+pub fn creature_id(cr : &Rc<RefCell<Creature>>) -> uint {
+    &**cr as *const RefCell<Creature> as uint
+}
+
+/// Map as a creature remembers it
+pub struct Memory {
+    tiles : hex2d::Map<Option<TileMemory>>,
+    creatures : Vec<CreatureMemory>,
+    /// Number of known tiles
+    explored : uint,
+}
+
+impl Memory {
+    pub fn new(map : &Map) -> Memory {
+        Memory {
+            tiles: map.clone(None),
+            creatures: Vec::new(),
+            explored: 0,
+        }
+    }
+
+    pub fn knows(&self, p : Point) -> bool {
+        self.tiles.at(p).is_some()
+    }
+
+    /// Number of tiles ever seen
+    pub fn explored(&self) -> uint {
+        self.explored
+    }
+
+    pub fn tile<'a>(&'a self, p : Point) -> Option<&'a TileMemory> {
+        self.tiles.at(p).as_ref()
+    }
+
+    /// Creatures remembered, each where it was seen last
+    pub fn creatures(&self) -> Items<CreatureMemory> {
+        self.creatures.iter()
+    }
+
+    /// Remember terrain of tile `p`
+    pub fn remember_tile(&mut self, map : &Map, p : Point, tick : uint) {
+        if !self.knows(p) {
+            self.explored += 1;
+        }
+        *self.tiles.mut_at(p) = Some(TileMemory {
+            tiletype: map.at(p).tiletype,
+            tick: tick,
+        });
+    }
+
+    /// Remember creature standing on tile `p`, if there's any
+    ///
+    /// Whoever was remembered at `p` before, and is not there any more, is
+    /// forgotten. Creatures that are currently borrowed (like the one
+    /// remembering) are skipped.
+    pub fn remember_creature_at(&mut self, map : &Map, p : Point, tick : uint) {
+        let rc = match map.at(p).creature {
+            Some(ref rc) => rc,
+            None => {
+                self.creatures.retain(|cr| cr.pos.p != p);
+                return;
+            }
+        };
+
+        let id = creature_id(rc);
+        let memory = match rc.try_borrow() {
+            Some(cr) => CreatureMemory {
+                id: id,
+                race: cr.race(),
+                is_player: cr.is_player(),
+                pos: *cr.pos(),
+                tick: tick,
+            },
+            None => return,
+        };
+
+        self.creatures.retain(|cr| cr.pos.p != p || cr.id == id);
+        match self.creatures.iter().position(|cr| cr.id == id) {
+            Some(i) => self.creatures.as_mut_slice()[i] = memory,
+            None => self.creatures.push(memory),
+        }
+    }
+}
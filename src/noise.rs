@@ -0,0 +1,118 @@
+// Copyright 2014 Dawid Ciężarkiewicz
+// See LICENSE file for more information
+
+use creature::Faction;
+use game::{Action,CreatureRef};
+use game::{Run,Move,Melee};
+use hex2d;
+use hex2d::Point;
+use map::Map;
+use std::collections::{RingBuf};
+use std::rand::Rng;
+
+/// Loudness of a creature dying
+pub static DEATH_LOUDNESS : uint = 8;
+
+/// Something creatures can hear
+#[deriving(Clone)]
+pub struct Noise {
+    /// Where the noise came from, or where it seemed to come from for
+    /// the one hearing it
+    pub p : Point,
+    pub loudness : uint,
+    /// Faction of whoever made the noise
+    pub faction : Faction,
+}
+
+impl Noise {
+    pub fn new(p : Point, loudness : uint, faction : Faction) -> Noise {
+        Noise {
+            p: p,
+            loudness: loudness,
+            faction: faction,
+        }
+    }
+}
+
+/// How loud performing an action is
+pub fn action_loudness(action : Action) -> uint {
+    match action {
+        Run(_) => 4,
+        Move(_) => 2,
+        Melee(_) => 6,
+        _ => 0,
+    }
+}
+
+/// Spread the noise over the map and let every creature in earshot
+/// `hear` it
+///
+/// Noise gets quieter with distance and walls muffle it. The further
+/// the creature is, the less accurate is the location it hears.
+pub fn propagate<R : Rng>(map : &Map, noise : &Noise, rng : &mut R,
+                          hear : |&CreatureRef, Noise|) {
+    let r = noise.loudness as int;
+    let side = (2 * r + 1) as uint;
+    let origin = noise.p;
+    let index = |p : Point| -> uint {
+        (p.x - origin.x + r) as uint * side + (p.y - origin.y + r) as uint
+    };
+
+    let mut loudness = Vec::from_elem(side * side, 0u);
+    let mut reached = vec!(origin);
+    let mut queue = RingBuf::new();
+
+    loudness.as_mut_slice()[index(origin)] = noise.loudness;
+    queue.push_back(origin);
+
+    loop {
+        let p = match queue.pop_front() {
+            Some(p) => p,
+            None => break,
+        };
+        let cur = loudness[index(p)];
+
+        for &n in p.neighbors().iter() {
+            let damping = map.at(map.wrap(n)).tiletype.noise_damping();
+            if cur <= damping {
+                continue;
+            }
+
+            let i = index(n);
+            if loudness[i] == 0 {
+                reached.push(n);
+            }
+            if loudness[i] < cur - damping {
+                loudness.as_mut_slice()[i] = cur - damping;
+                queue.push_back(n);
+            }
+        }
+    }
+
+    for &p in reached.iter() {
+        if p == origin {
+            continue;
+        }
+
+        match map.at(map.wrap(p)).creature {
+            Some(ref cr) => {
+                let heard = loudness[index(p)];
+                let p = approximate(map, origin, noise.loudness - heard, rng);
+                hear(cr, Noise::new(p, heard, noise.faction));
+            },
+            None => {}
+        }
+    }
+}
+
+/// Location of a noise as heard after it lost `lost` loudness on the way
+fn approximate<R : Rng>(map : &Map, p : Point, lost : uint, rng : &mut R) -> Point {
+    let mut p = p;
+
+    for _ in range(0, lost / 3) {
+        let dir = hex2d::ALL_DIRECTIONS[rng.gen_range(0u, 6)];
+        p = map.wrap(p + dir);
+    }
+
+    p
+}
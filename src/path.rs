@@ -0,0 +1,239 @@
+// Copyright 2014 Dawid Ciężarkiewicz
+// See LICENSE file for more information
+
+//! Path finding
+//!
+//! A* over positions (point and facing), so that turning is taken into
+//! account. Costs are in ticks and mirror `CreatureState::action_delay`.
+
+use creature::Race;
+use game::Action;
+use game::{Turn,Move};
+use hex2d::{Point,Position};
+use hex2d::{Left,Right,Forward};
+use map;
+use map::{Map,PointSet,TileType,Floor};
+use memory::Memory;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap,HashMap};
+
+static TURN_COST : uint = 1;
+static MOVE_COST : uint = 2;
+/// Extra cost of stepping on a harmful tile
+static HARMFUL_COST : uint = 20;
+/// Extra cost of stepping on a tile occupied by another creature
+static OCCUPIED_COST : uint = 8;
+/// Give up after expanding that many positions
+static MAX_EXPANDED : uint = 4000;
+
+/// Why there's no path
+pub enum NoPath {
+    /// Every reachable position was tried
+    Unreachable,
+    /// Gave up after expanding `MAX_EXPANDED` positions
+    TooFar,
+}
+
+/// What the one looking for a path knows about the map
+pub enum Knowledge<'a> {
+    /// The map as it is
+    Everything,
+    /// Terrain as remembered, and creatures only on the tiles in sight
+    Remembered(&'a Memory, &'a PointSet),
+}
+
+impl<'a> Knowledge<'a> {
+    /// Terrain at `p` and is someone standing there, as far as known
+    ///
+    /// Terrain never seen is hoped to be floor.
+    fn tile(&self, map : &Map, p : Point) -> (TileType, bool) {
+        match *self {
+            Everything => (map.at(p).tiletype, map.at(p).creature.is_some()),
+            Remembered(memory, visible) => match memory.tile(p) {
+                Some(tile) => (tile.tiletype, visible.contains(p) && map.at(p).creature.is_some()),
+                None => (Floor, false),
+            },
+        }
+    }
+}
+
+struct Node {
+    estimate : uint,
+    cost : uint,
+    pos : Position,
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other : &Node) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl Eq for Node {}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other : &Node) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Node {
+    /// Reversed, so the heap pops the cheapest node first
+    fn cmp(&self, other : &Node) -> Ordering {
+        other.estimate.cmp(&self.estimate)
+    }
+}
+
+struct Visit {
+    cost : uint,
+    /// Previous position and action that led from it
+    prev : Option<(Position, Action)>,
+    done : bool,
+}
+
+fn index(map : &Map, pos : Position) -> uint {
+    ((pos.p.y as uint * map.width()) + pos.p.x as uint) * 6 + pos.dir.to_uint()
+}
+
+/// Cost of stepping on `p`, `None` if impossible
+fn step_cost(map : &Map, knowledge : &Knowledge, from : Position, p : Point, race : Race,
+             is_goal : bool) -> Option<uint> {
+    let (tiletype, occupied) = knowledge.tile(map, p);
+
+    if !tiletype.is_passable_for(race) {
+        return None;
+    }
+
+    let mut cost = MOVE_COST + tiletype.move_delay();
+
+    if tiletype.is_harmful() {
+        cost += HARMFUL_COST;
+    }
+
+    if occupied && !is_goal {
+        /* Creatures right next to us are not going to move away in time */
+        if map::distance(from.p, p) <= 1 {
+            return None;
+        }
+        cost += OCCUPIED_COST;
+    }
+
+    Some(cost)
+}
+
+fn search(
+    map : &Map, knowledge : &Knowledge, from : Position, race : Race,
+    is_goal : |Point| -> bool,
+    heuristic : |Point| -> uint,
+    ) -> Result<Vec<Action>, NoPath> {
+
+    let start = map.wrap(from);
+    let mut visits : HashMap<uint, Visit> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+
+    visits.insert(index(map, start), Visit { cost: 0, prev: None, done: false });
+    queue.push(Node { estimate: heuristic(start.p), cost: 0, pos: start });
+
+    let mut expanded = 0u;
+
+    loop {
+        let node = match queue.pop() {
+            Some(node) => node,
+            None => return Err(Unreachable),
+        };
+
+        let i = index(map, node.pos);
+        match visits.get_mut(&i) {
+            Some(visit) => {
+                if visit.done || visit.cost < node.cost {
+                    continue;
+                }
+                visit.done = true;
+            },
+            None => {},
+        }
+
+        if node.pos.p != start.p && is_goal(node.pos.p) {
+            return Ok(reconstruct(map, &visits, node.pos));
+        }
+
+        expanded += 1;
+        if expanded > MAX_EXPANDED {
+            return Err(TooFar);
+        }
+
+        let mut next : Vec<(Position, Action, uint)> = Vec::new();
+
+        for &dir in [Left, Right].iter() {
+            next.push((map.wrap(node.pos + dir), Turn(dir), TURN_COST));
+        }
+
+        for &dir in [Forward, Left, Right].iter() {
+            let p = map.wrap(node.pos.p + (node.pos.dir + dir));
+            match step_cost(map, knowledge, start, p, race, is_goal(p)) {
+                Some(cost) => next.push((Position::new(p, node.pos.dir), Move(dir), cost)),
+                None => {}
+            }
+        }
+
+        for &(pos, action, cost) in next.iter() {
+            let cost = node.cost + cost;
+            let j = index(map, pos);
+
+            let better = match visits.get(&j) {
+                Some(visit) => cost < visit.cost,
+                None => true,
+            };
+
+            if better {
+                visits.insert(j, Visit {
+                    cost: cost,
+                    prev: Some((node.pos, action)),
+                    done: false,
+                });
+                queue.push(Node {
+                    estimate: cost + heuristic(pos.p),
+                    cost: cost,
+                    pos: pos,
+                });
+            }
+        }
+    }
+}
+
+fn reconstruct(map : &Map, visits : &HashMap<uint, Visit>, to : Position) -> Vec<Action> {
+    let mut actions = Vec::new();
+    let mut pos = to;
+
+    loop {
+        match visits.get(&index(map, pos)).and_then(|visit| visit.prev) {
+            Some((prev, action)) => {
+                actions.push(action);
+                pos = prev;
+            },
+            None => break,
+        }
+    }
+
+    actions.reverse();
+    actions
+}
+
+/// Actions leading from `from` to `to`
+///
+/// `to` can be occupied (eg. by a creature being chased).
+pub fn path_to(map : &Map, knowledge : &Knowledge, from : Position, to : Point,
+               race : Race) -> Result<Vec<Action>, NoPath> {
+    let to = map.wrap(to);
+    search(map, knowledge, from, race,
+           |p| p == to,
+           |p| map::distance(p, to) * MOVE_COST)
+}
+
+/// Actions leading from `from` to the nearest point satisfying `is_goal`
+pub fn path_to_nearest(
+    map : &Map, knowledge : &Knowledge, from : Position, race : Race,
+    is_goal : |Point| -> bool
+    ) -> Result<Vec<Action>, NoPath> {
+    search(map, knowledge, from, race, is_goal, |_| 0)
+}
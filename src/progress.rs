@@ -0,0 +1,121 @@
+// Copyright 2014 Dawid Ciężarkiewicz
+// See LICENSE file for more information
+
+use serialize::json;
+use std::io::File;
+use std::io::IoResult;
+
+/// Experience needed for every next level, times the current level
+static XP_PER_LEVEL : uint = 10;
+
+/// Bonus picked on level up
+#[deriving(Clone)]
+#[deriving(PartialEq)]
+#[deriving(Show)]
+#[deriving(Encodable)]
+#[deriving(Decodable)]
+pub enum Perk {
+    /// Running takes less time
+    FastRunner,
+    /// Seeing further
+    KeenEyes,
+    /// Surprise attacks deal even more damage
+    Backstabber,
+}
+
+pub static ALL_PERKS : [Perk, ..3] = [FastRunner, KeenEyes, Backstabber];
+
+impl Perk {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FastRunner => "fast runner",
+            KeenEyes => "keen eyes",
+            Backstabber => "backstabber",
+        }
+    }
+}
+
+/// Experience, level and perks of the player
+#[deriving(Clone)]
+#[deriving(Encodable)]
+#[deriving(Decodable)]
+pub struct Progress {
+    pub xp : uint,
+    pub level : uint,
+    pub perks : Vec<Perk>,
+    /// Level ups that still need a perk chosen
+    pub perks_pending : uint,
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress {
+            xp: 0,
+            level: 1,
+            perks: Vec::new(),
+            perks_pending: 0,
+        }
+    }
+
+    /// Experience needed to get to the next level
+    pub fn xp_to_level(&self) -> uint {
+        XP_PER_LEVEL * self.level
+    }
+
+    /// Add `xp` experience
+    ///
+    /// Returns number of levels gained. Every level up comes with a perk
+    /// to pick, as long as there are perks left.
+    pub fn gain(&mut self, xp : uint) -> uint {
+        let mut levels = 0;
+
+        self.xp += xp;
+        while self.xp >= self.xp_to_level() {
+            self.xp -= self.xp_to_level();
+            self.level += 1;
+            if self.perks.len() + self.perks_pending < ALL_PERKS.len() {
+                self.perks_pending += 1;
+            }
+            levels += 1;
+        }
+
+        levels
+    }
+
+    pub fn has(&self, perk : Perk) -> bool {
+        self.perks.contains(&perk)
+    }
+
+    /// Take `perk` for a level up
+    ///
+    /// Fails if there's no level up to pick a perk for, or the perk
+    /// was already taken.
+    pub fn choose(&mut self, perk : Perk) -> bool {
+        if self.perks_pending == 0 || self.has(perk) {
+            return false;
+        }
+
+        self.perks_pending -= 1;
+        self.perks.push(perk);
+        true
+    }
+
+    pub fn load(path : &Path) -> Option<Progress> {
+        let source = match File::open(path).read_to_string() {
+            Ok(source) => source,
+            Err(_) => return None,
+        };
+
+        match json::decode(source.as_slice()) {
+            Ok(progress) => Some(progress),
+            Err(err) => {
+                error!("save {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path : &Path) -> IoResult<()> {
+        File::create(path).write_str(json::encode(self).as_slice())
+    }
+}
@@ -0,0 +1,334 @@
+// Copyright 2014 Dawid Ciężarkiewicz
+// See LICENSE file for more information
+
+//! Creatures controlled by Lua scripts
+//!
+//! A script defines a global function `act(me)`, called every time the
+//! creature decides what to do. It returns the action as a string:
+//! `"wait"`, `"turn left|right"` or `"move|run|melee"` followed by
+//! `"forward|backward|left|right"`.
+//!
+//! `me` is a fresh table every time, so changing it does nothing to the
+//! game:
+//!
+//! * `id` - number of the creature, unique among those using the script
+//! * `race` - `"human"`, `"scout"`, `"grunt"` or `"heavy"`
+//! * `x`, `y` - position on the map
+//! * `health`, `max_health`
+//! * `hurt` - health is low enough for the race to rather run away
+//! * `tick` - current game tick
+//! * `roll` - random number in `[0, 1)`
+//! * `ahead`, `left`, `right` - tiles one move away in these directions
+//! * `tiles` - list of visible tiles
+//! * `creatures` - list of visible creatures, except itself
+//!
+//! A tile has `x`, `y`, `type` (eg. `"wall"` or `"deep water"`), `light`
+//! and `passable`. A creature has `x`, `y`, `race`, `health`, `distance`,
+//! `hostile`, `ally` and `at` - `"ahead"`, `"left"` or `"right"` when it
+//! can be attacked from where `me` stands, `nil` otherwise.
+//!
+//! All creatures using a script share one Lua state, so globals set by
+//! the script are shared too; use `id` to keep things apart.
+//!
+//! Scripts are reloaded when the file changes. Errors are logged once
+//! and make the creatures wait until the script is fixed.
+
+use ai::Actor;
+use creature::CreatureState;
+use game::Action;
+use game::{Turn,Move,Run,Melee,Wait};
+use hex2d::Direction;
+use hex2d::{Left,Right,Forward,Backward};
+use hex2d::Point;
+use lua;
+use map;
+use std::cell::RefCell;
+use std::io::fs;
+use std::rand;
+use std::rand::Rng;
+use std::rc::Rc;
+
+/// Actions between checking the script file for changes
+static RELOAD_TICKS : uint = 20;
+
+fn parse_direction(word : &str) -> Result<Direction, String> {
+    match word {
+        "forward" => Ok(Forward),
+        "backward" => Ok(Backward),
+        "left" => Ok(Left),
+        "right" => Ok(Right),
+        _ => Err(format!("unknown direction `{}`", word)),
+    }
+}
+
+fn parse_action(source : &str) -> Result<Action, String> {
+    let words : Vec<&str> = source.words().collect();
+
+    match words.as_slice() {
+        ["wait"] => Ok(Wait),
+        ["turn", dir] => match try!(parse_direction(dir)) {
+            dir@Left|dir@Right => Ok(Turn(dir)),
+            _ => Err("can only turn left or right".to_string()),
+        },
+        ["move", dir] => Ok(Move(try!(parse_direction(dir)))),
+        ["run", dir] => Ok(Run(try!(parse_direction(dir)))),
+        ["melee", dir] => Ok(Melee(try!(parse_direction(dir)))),
+        _ => Err(format!("unknown action `{}`", source.trim())),
+    }
+}
+
+fn set_number(lua : &mut lua::State, key : &str, value : f64) {
+    lua.pushnumber(value);
+    lua.setfield(-2, key);
+}
+
+fn set_string(lua : &mut lua::State, key : &str, value : &str) {
+    lua.pushstring(value);
+    lua.setfield(-2, key);
+}
+
+fn set_bool(lua : &mut lua::State, key : &str, value : bool) {
+    lua.pushboolean(value);
+    lua.setfield(-2, key);
+}
+
+/// Push a table describing the tile at `p`
+fn push_tile(lua : &mut lua::State, map : &map::Map, me : &CreatureState, p : Point) {
+    let tile = map.at(p);
+
+    lua.newtable();
+    set_number(lua, "x", p.x as f64);
+    set_number(lua, "y", p.y as f64);
+    set_string(lua, "type", tile.tiletype.name());
+    set_number(lua, "light", tile.light as f64);
+    set_bool(lua, "passable", tile.is_passable_type_for(me.race));
+}
+
+/// Push `me` for `act`, as seen by the creature in state `me`
+fn push_view(lua : &mut lua::State, map : &map::Map, me : &CreatureState,
+             id : uint, roll : f64) {
+    lua.newtable();
+    set_number(lua, "id", id as f64);
+    set_string(lua, "race", me.race.name());
+    set_number(lua, "x", me.pos.p.x as f64);
+    set_number(lua, "y", me.pos.p.y as f64);
+    set_number(lua, "health", me.health() as f64);
+    set_number(lua, "max_health", me.max_health() as f64);
+    set_bool(lua, "hurt", me.health_fraction() < me.race.flee_health());
+    set_number(lua, "tick", me.now() as f64);
+    set_number(lua, "roll", roll);
+
+    let reach = [("ahead", Forward), ("left", Left), ("right", Right)];
+    let reach : Vec<(&str, Point)> = reach.iter().map(|&(name, dir)| {
+        (name, map.wrap(me.pos.p + (me.pos.dir + dir)))
+    }).collect();
+
+    for &(name, p) in reach.iter() {
+        push_tile(lua, map, me, p);
+        lua.setfield(-2, name);
+    }
+
+    lua.newtable();
+    for (i, &p) in me.visible.iter().enumerate() {
+        push_tile(lua, map, me, p);
+        lua.rawseti(-2, i as i32 + 1);
+    }
+    lua.setfield(-2, "tiles");
+
+    lua.newtable();
+    let mut n = 0;
+    for &p in me.visible.iter() {
+        if p == me.pos.p {
+            continue;
+        }
+
+        let other = match map.at(p).creature.as_ref().and_then(|cr| cr.try_borrow()) {
+            Some(other) => other,
+            None => continue,
+        };
+        if !other.is_alive() {
+            continue;
+        }
+
+        lua.newtable();
+        set_number(lua, "x", p.x as f64);
+        set_number(lua, "y", p.y as f64);
+        set_string(lua, "race", other.race().name());
+        set_number(lua, "health", other.health() as f64);
+        set_number(lua, "distance", map::distance(me.pos.p, p) as f64);
+        set_bool(lua, "hostile", me.faction.is_hostile_to(other.faction()));
+        set_bool(lua, "ally", me.faction == other.faction());
+        match reach.iter().find(|&&(_, at)| at == p) {
+            Some(&(name, _)) => set_string(lua, "at", name),
+            None => {}
+        }
+
+        n += 1;
+        lua.rawseti(-2, n);
+    }
+    lua.setfield(-2, "creatures");
+}
+
+/// Call `act` and make an `Action` of what it returned
+fn call_act(lua : &mut lua::State, map : &map::Map, me : &CreatureState,
+            id : uint, roll : f64) -> Result<Action, String> {
+    lua.getglobal("act");
+    push_view(lua, map, me, id, roll);
+
+    match lua.pcall(1, 1, 0) {
+        Ok(()) => {},
+        Err(err) => return Err(format!("{}", err)),
+    }
+
+    let action = match lua.tostring(-1) {
+        Some(action) => parse_action(action),
+        None => Err("`act` has to return an action".to_string()),
+    };
+    lua.pop(1);
+
+    action
+}
+
+/// Lua state running a script file, shared by all the creatures using it
+pub struct Script {
+    path : Path,
+    /// `None` until the script loads fine
+    lua : Option<lua::State>,
+    /// Modification time of the loaded script file
+    modified : Option<u64>,
+    /// Actions left until checking the file for changes
+    reload_in : uint,
+    /// Was the last problem with the script reported already
+    failed : bool,
+    /// Number for the next creature using the script
+    next_id : uint,
+}
+
+impl Script {
+    pub fn new(path : Path) -> Rc<RefCell<Script>> {
+        Rc::new(RefCell::new(Script {
+            path: path,
+            lua: None,
+            modified: None,
+            reload_in: 0,
+            failed: false,
+            next_id: 0,
+        }))
+    }
+
+    fn report(&mut self, msg : String) {
+        if !self.failed {
+            error!("script {}: {}", self.path.display(), msg);
+            self.failed = true;
+        }
+    }
+
+    fn load(&self) -> Result<lua::State, String> {
+        let mut lua = lua::State::new();
+        lua.openlibs();
+
+        match lua.loadfile(Some(&self.path)) {
+            Ok(()) => {},
+            Err(err) => return Err(format!("{}", err)),
+        }
+        match lua.pcall(0, 0, 0) {
+            Ok(()) => {},
+            Err(err) => return Err(format!("{}", err)),
+        }
+
+        lua.getglobal("act");
+        let defined = lua.isfunction(-1);
+        lua.pop(1);
+        if !defined {
+            return Err("no `act` function".to_string());
+        }
+
+        Ok(lua)
+    }
+
+    fn reload_if_changed(&mut self) {
+        if self.reload_in > 0 {
+            self.reload_in -= 1;
+            return;
+        }
+        self.reload_in = RELOAD_TICKS;
+
+        let modified = match fs::stat(&self.path) {
+            Ok(stat) => stat.modified,
+            Err(err) => {
+                self.modified = None;
+                self.lua = None;
+                self.report(format!("{}", err));
+                return;
+            }
+        };
+
+        if self.modified == Some(modified) {
+            return;
+        }
+        self.modified = Some(modified);
+
+        match self.load() {
+            Ok(lua) => {
+                self.lua = Some(lua);
+                self.failed = false;
+            },
+            Err(msg) => {
+                self.lua = None;
+                self.report(msg);
+            },
+        }
+    }
+
+    /// Action of creature number `id`, `Wait` if the script fails
+    fn act(&mut self, map : &map::Map, me : &CreatureState, id : uint, roll : f64) -> Action {
+        self.reload_if_changed();
+
+        let action = match self.lua {
+            Some(ref mut lua) => call_act(lua, map, me, id, roll),
+            None => return Wait,
+        };
+
+        match action {
+            Ok(action) => action,
+            Err(msg) => {
+                self.report(msg);
+                Wait
+            },
+        }
+    }
+}
+
+/// Actor asking a script what to do
+pub struct ScriptActor {
+    script : Rc<RefCell<Script>>,
+    id : uint,
+    /// For `roll`, seeded from the game's RNG
+    rng : rand::XorShiftRng,
+}
+
+impl ScriptActor {
+    pub fn new<R : Rng>(script : Rc<RefCell<Script>>, rng : &mut R) -> ScriptActor {
+        let id = {
+            let mut script = script.borrow_mut();
+            script.next_id += 1;
+            script.next_id
+        };
+
+        ScriptActor {
+            script: script,
+            id: id,
+            rng: rng.gen(),
+        }
+    }
+}
+
+impl Actor for ScriptActor {
+    fn get_action(&mut self, map : &map::Map, me : &CreatureState) -> Option<Action> {
+        let roll = self.rng.gen::<f64>();
+        Some(self.script.borrow_mut().act(map, me, self.id, roll))
+    }
+
+    fn proceed_visible(&mut self, _ : &map::Map, _ : &CreatureState, _ : Point) {
+    }
+}
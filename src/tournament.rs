@@ -0,0 +1,152 @@
+// Copyright 2014 Dawid Ciężarkiewicz
+// See LICENSE file for more information
+
+//! Headless games comparing two actors playing the player's character
+//!
+//! Both contestants play the same seeded games. A contestant is either
+//! the name of a built-in AI, or a path to a Lua script.
+
+use ai::{Actor,AIActor};
+use ai::{scout_tree,grunt_tree,heavy_tree};
+use creature::{Human,Scout,Grunt,Heavy};
+use game::GameState;
+use script::{Script,ScriptActor};
+use std::rand::{SeedableRng,XorShiftRng};
+
+/// Ticks after which a game ends even if the player survived
+static MAX_TICKS : uint = 2000;
+
+/// Statistics summed over all the games played by a contestant
+struct Stats {
+    games : uint,
+    survived : uint,
+    ticks : uint,
+    /// Indexed by `Race::to_uint`
+    kills : [uint, ..4],
+    damage_dealt : uint,
+    damage_taken : uint,
+    explored : uint,
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats {
+            games: 0,
+            survived: 0,
+            ticks: 0,
+            kills: [0, ..4],
+            damage_dealt: 0,
+            damage_taken: 0,
+            explored: 0,
+        }
+    }
+
+    fn average(&self, total : uint) -> f32 {
+        if self.games == 0 {
+            0.0
+        } else {
+            total as f32 / self.games as f32
+        }
+    }
+}
+
+/// Makes a built-in AI for the player's character
+type Constructor = fn(&mut XorShiftRng) -> Box<Actor+'static>;
+
+fn default_ai(rng : &mut XorShiftRng) -> Box<Actor+'static> {
+    box AIActor::for_race(Human, rng)
+}
+
+fn scout_ai(rng : &mut XorShiftRng) -> Box<Actor+'static> {
+    box AIActor::player(scout_tree(), rng)
+}
+
+fn grunt_ai(rng : &mut XorShiftRng) -> Box<Actor+'static> {
+    box AIActor::player(grunt_tree(), rng)
+}
+
+fn heavy_ai(rng : &mut XorShiftRng) -> Box<Actor+'static> {
+    box AIActor::player(heavy_tree(), rng)
+}
+
+/// Built-in AIs that can take part, by name
+///
+/// To compare a change to the AI with the version it replaces, register
+/// both here under different names.
+fn builtin_ais() -> Vec<(&'static str, Constructor)> {
+    vec!(
+        ("ai", default_ai as Constructor),
+        ("scout", scout_ai as Constructor),
+        ("grunt", grunt_ai as Constructor),
+        ("heavy", heavy_ai as Constructor),
+    )
+}
+
+fn actor_for(contestant : &str, rng : &mut XorShiftRng) -> Box<Actor+'static> {
+    match builtin_ais().iter().find(|&&(name, _)| name == contestant) {
+        Some(&(_, constructor)) => constructor(rng),
+        None => box ScriptActor::new(Script::new(Path::new(contestant)), rng) as Box<Actor+'static>,
+    }
+}
+
+/// Play one game with the seed `seed` and add up its results to `stats`
+fn play(seed : u32, contestant : &str, stats : &mut Stats) {
+    let mut game = GameState::with_seed(seed);
+    game.randomize_map();
+
+    let pl = match game.player {
+        Some(ref pl) => pl.clone(),
+        None => return,
+    };
+    let mut rng : XorShiftRng = SeedableRng::from_seed([0x2f6b91e3, 0x5cd1a804, 0x71e4c3b9, seed]);
+    pl.borrow_mut().set_actor(actor_for(contestant, &mut rng));
+
+    let mut ticks = 0;
+    while ticks < MAX_TICKS && pl.borrow().is_alive() {
+        if game.tick() {
+            /* Actor gave up deciding; nothing will change anymore */
+            break;
+        }
+        ticks += 1;
+    }
+
+    let pl = pl.borrow();
+    stats.games += 1;
+    stats.ticks += ticks;
+    if pl.is_alive() {
+        stats.survived += 1;
+    }
+    for race in pl.kills().iter() {
+        stats.kills[race.to_uint()] += 1;
+    }
+    stats.damage_dealt += pl.damage_dealt();
+    stats.damage_taken += pl.damage_taken();
+    stats.explored += pl.memory().map_or(0, |memory| memory.explored());
+}
+
+fn print_row(name : &str, a : f32, b : f32) {
+    println!("{:<16}{:>12.1}{:>12.1}", name, a, b);
+}
+
+/// Play `games` games with each contestant and print the comparison
+pub fn run(games : uint, a : &str, b : &str) {
+    let mut stats_a = Stats::new();
+    let mut stats_b = Stats::new();
+
+    for seed in range(0, games) {
+        play(seed as u32, a, &mut stats_a);
+        play(seed as u32, b, &mut stats_b);
+    }
+
+    println!("{:<16}{:>12}{:>12}", "", a, b);
+    print_row("games", stats_a.games as f32, stats_b.games as f32);
+    print_row("survived", stats_a.survived as f32, stats_b.survived as f32);
+    print_row("ticks", stats_a.average(stats_a.ticks), stats_b.average(stats_b.ticks));
+    for &(name, race) in [("kills: scout", Scout), ("kills: grunt", Grunt), ("kills: heavy", Heavy)].iter() {
+        let i = race.to_uint();
+        print_row(name, stats_a.average(stats_a.kills[i]), stats_b.average(stats_b.kills[i]));
+    }
+    print_row("damage dealt", stats_a.average(stats_a.damage_dealt), stats_b.average(stats_b.damage_dealt));
+    print_row("damage taken", stats_a.average(stats_a.damage_taken), stats_b.average(stats_b.damage_taken));
+    print_row("tiles explored", stats_a.average(stats_a.explored), stats_b.average(stats_b.explored));
+}
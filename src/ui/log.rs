@@ -0,0 +1,171 @@
+// Copyright 2014 Dawid Ciężarkiewicz
+// See LICENSE file for more information
+
+use creature::Human;
+use game::{Event, Subject};
+use game::{Hit, Killed, Suffered, Died, Blocked, Smashed, Spotted, LevelUp};
+use std::collections::RingBuf;
+
+/// Messages kept in the history
+static HISTORY_LENGTH : uint = 200;
+
+/// How to call `subject` in a message
+fn name(subject : &Subject) -> String {
+    if subject.is_player {
+        "you".to_string()
+    } else if subject.race == Human {
+        "your companion".to_string()
+    } else {
+        format!("the {}", subject.race.name())
+    }
+}
+
+/// `verb` in the form matching `subject`, eg. "hit" or "hits"
+fn verb(subject : &Subject, verb : &str) -> String {
+    if subject.is_player {
+        verb.to_string()
+    } else {
+        format!("{}s", verb)
+    }
+}
+
+fn capitalize(s : String) -> String {
+    let mut chars = s.as_slice().chars();
+    match chars.next() {
+        Some(c) => format!("{}{}", c.to_uppercase(), chars.collect::<String>()),
+        None => s.clone(),
+    }
+}
+
+/// Message telling the player about `event`, if it concerns the player
+pub fn describe(event : &Event) -> Option<String> {
+    let msg = match *event {
+        Hit(ref by, ref target, damage) if by.is_player || target.is_player => {
+            format!("{} {} {} for {}", name(by), verb(by, "hit"), name(target), damage)
+        },
+        Killed(ref by, ref target) if by.is_player || target.is_player => {
+            format!("{} {} {}", name(by), verb(by, "kill"), name(target))
+        },
+        Suffered(ref who, damage) if who.is_player => {
+            format!("you lose {} health", damage)
+        },
+        Died(ref who) if who.is_player => "you die".to_string(),
+        Blocked(ref who, tiletype) if who.is_player => {
+            format!("the {} blocks your way", tiletype.name())
+        },
+        Smashed(ref who, tiletype) if who.is_player => {
+            format!("you smash the {}", tiletype.name())
+        },
+        Spotted(race) => format!("you see a {}", race.name()),
+        LevelUp(level, true) => format!("you reach level {}! press 1, 2 or 3 to pick a perk", level),
+        LevelUp(level, false) => format!("you reach level {}", level),
+        _ => return None,
+    };
+
+    Some(capitalize(msg))
+}
+
+/// Messages shown to the player, oldest first
+///
+/// The same message repeated is kept once, with a count.
+pub struct MessageLog {
+    messages : RingBuf<(String, uint)>,
+}
+
+impl MessageLog {
+    pub fn new() -> MessageLog {
+        MessageLog {
+            messages: RingBuf::new(),
+        }
+    }
+
+    pub fn push(&mut self, msg : String) {
+        let count = match self.messages.back() {
+            Some(&(ref last, count)) if *last == msg => count,
+            _ => 0,
+        };
+
+        if count > 0 {
+            self.messages.pop_back();
+        } else if self.messages.len() >= HISTORY_LENGTH {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((msg, count + 1));
+    }
+
+    pub fn len(&self) -> uint {
+        self.messages.len()
+    }
+
+    /// Last `n` messages, ready to be shown
+    pub fn recent(&self, n : uint) -> Vec<String> {
+        self.page(n, 0)
+    }
+
+    /// `n` messages ending `back` messages before the latest one
+    pub fn page(&self, n : uint, back : uint) -> Vec<String> {
+        let end = if self.messages.len() > back { self.messages.len() - back } else { 0 };
+        let skip = if end > n { end - n } else { 0 };
+
+        self.messages.iter().skip(skip).take(end - skip).map(|&(ref msg, count)| {
+            if count > 1 {
+                format!("{} (x{})", msg, count)
+            } else {
+                msg.clone()
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MessageLog, HISTORY_LENGTH};
+
+    fn log_of(messages : &[&str]) -> MessageLog {
+        let mut log = MessageLog::new();
+        for msg in messages.iter() {
+            log.push(msg.to_string());
+        }
+        log
+    }
+
+    #[test]
+    fn repeats_are_merged() {
+        let log = log_of(&["a", "b", "b", "b", "a"]);
+
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.recent(3), vec!("a".to_string(), "b (x3)".to_string(), "a".to_string()));
+    }
+
+    #[test]
+    fn recent_takes_the_latest() {
+        let log = log_of(&["a", "b", "c"]);
+
+        assert_eq!(log.recent(2), vec!("b".to_string(), "c".to_string()));
+        assert_eq!(log.recent(10).len(), 3);
+        assert!(MessageLog::new().recent(5).is_empty());
+    }
+
+    #[test]
+    fn oldest_are_dropped() {
+        let mut log = MessageLog::new();
+        for i in range(0, HISTORY_LENGTH + 10) {
+            log.push(format!("{}", i));
+        }
+
+        assert_eq!(log.len(), HISTORY_LENGTH);
+        assert_eq!(log.page(1, HISTORY_LENGTH - 1), vec!("10".to_string()));
+        assert_eq!(log.recent(1), vec!(format!("{}", HISTORY_LENGTH + 9)));
+    }
+
+    #[test]
+    fn pages_stay_in_bounds() {
+        let log = log_of(&["a", "b", "c", "d"]);
+
+        assert_eq!(log.page(2, 1), vec!("b".to_string(), "c".to_string()));
+        assert_eq!(log.page(2, 3), vec!("a".to_string()));
+        assert!(log.page(2, 4).is_empty());
+        assert!(log.page(2, 100).is_empty());
+        assert_eq!(log.page(100, 0).len(), 4);
+    }
+}
@@ -1,6 +1,8 @@
 // Copyright 2014 Dawid Ciężarkiewicz
 // See LICENSE file for more information
 
+pub mod font;
+pub mod log;
 pub mod piston;
 
 pub trait UI : Drop {
@@ -9,11 +9,13 @@ use cgmath::Point as CgPoint;
 use cgmath::{Transform, AffineMatrix3};
 use cgmath::Vector;
 use creature::Creature;
-use creature::{Grunt, Scout, Heavy, Human};
+use creature::{Race, Grunt, Scout, Heavy, Human};
 use device;
 use device::draw::CommandBuffer;
 use gfx::GlCommandBuffer;
 use gfx::GlDevice;
+use ai::{Actor,ActionQueue,InputActor};
+use ai::{Order,Follow,Hold,AttackAt};
 use game::Action;
 use game::GameState;
 use game::{Run, Move, Turn, Melee, Wait};
@@ -21,13 +23,26 @@ use gfx;
 use gfx::{Device, DeviceHelper};
 use hex2d::{Forward, Backward, Left, Right, Direction, AbsoluteDirection};
 use hex2d::{North, Position, Point};
+use input;
 use input::keyboard as key;
-use map::{Wall, Sand, GlassWall, Floor};
+use input::mouse;
+use effect::Effect;
+use effect::{Stunned, Bleeding, Slowed, Blinded, Enraged};
+use map::{Map,TileType};
+use progress::{Perk,ALL_PERKS};
+use map::{Wall, Sand, GlassWall, Floor, Water, DeepWater, Lava, Debris, Torch};
+use path;
 use std;
 use glfw_window::GlfwWindow as Window;
 use std::collections::{RingBuf};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::cmp;
 use std::num::{zero, one};
 use time;
+use ui::font;
+use ui::log;
+use ui::log::MessageLog;
 use obj;
 use genmesh;
 use genmesh::Indexer;
@@ -53,6 +68,7 @@ use piston::input::{
     Press,
     Release,
     Keyboard,
+    Mouse,
 };
 
 use std::mem::size_of;
@@ -68,6 +84,9 @@ struct Vertex {
     normal: [f32, ..3],
 }
 
+/// Vertex of degenerate triangles, drawing nothing
+static BLANK_VERTEX : Vertex = Vertex { pos: [0.0, 0.0, 0.0], normal: [0.0, 0.0, 1.0] };
+
 impl std::cmp::PartialEq for Vertex {
     fn eq(&self, other: &Vertex) -> bool {
         self.pos.as_slice() == other.pos.as_slice() &&
@@ -147,7 +166,18 @@ struct Renderer<C : device::draw::CommandBuffer, D: gfx::Device<C>> {
     graphics: gfx::Graphics<D, C>,
     tile_batch: Batch,
     creature_batch: Batch,
+    /// Unit square, for everything drawn over the map
+    quad_batch: Batch,
+    font: font::Font,
+    /// Text queued this frame, as triangles in window pixels
+    text: Vec<Vertex>,
+    text_buffer: gfx::BufferHandle<Vertex>,
+    text_batch: Batch,
+    /// Vertices of text drawn in the last frame
+    text_drawn: uint,
     projection: Matrix4<f32>,
+    /// Window pixels, origin in the top left corner
+    overlay_projection: Matrix4<f32>,
     view: Matrix4<f32>,
     frame: gfx::Frame,
     cd: gfx::ClearData,
@@ -160,10 +190,44 @@ static WALL_COLOR : Color = [0.3f32, 0.2, 0.0, 1.0];
 static GLASSWALL_COLOR : Color = [0.7f32, 0.7, 0.95, 1.0];
 static SAND_COLOR : Color = [1.0f32, 1.0, 0.8, 1.0];
 static FLOOR_COLOR : Color = [1.0f32, 0.9, 0.9, 1.0];
+static WATER_COLOR : Color = [0.5f32, 0.7, 1.0, 1.0];
+static DEEPWATER_COLOR : Color = [0.1f32, 0.2, 0.7, 1.0];
+static LAVA_COLOR : Color = [1.0f32, 0.3, 0.0, 1.0];
+static DEBRIS_COLOR : Color = [0.8f32, 0.8, 0.85, 1.0];
+static TORCH_COLOR : Color = [1.0f32, 0.8, 0.2, 1.0];
+static HOVER_COLOR : Color = [1.0f32, 1.0, 0.0, 1.0];
+/// How much is the hovered tile raised above its neighbors
+static HOVER_HEIGHT : f32 = 0.05f32;
 static SCOUT_COLOR : Color = [0.0f32, 0.8, 0.0, 1.0];
 static GRUNT_COLOR : Color = [0.0f32, 0.6, 0.0, 1.0];
 static HEAVY_COLOR : Color = [0.0f32, 0.4, 0.0, 1.0];
+static COMPANION_COLOR : Color = [0.0f32, 0.5, 1.0, 1.0];
+
+static STUNNED_TINT : Color = [1.0f32, 1.0, 0.0, 1.0];
+static BLEEDING_TINT : Color = [0.5f32, 0.0, 0.0, 1.0];
+static SLOWED_TINT : Color = [0.6f32, 0.6, 1.0, 1.0];
+static BLINDED_TINT : Color = [0.2f32, 0.2, 0.2, 1.0];
+static ENRAGED_TINT : Color = [1.0f32, 0.4, 0.0, 1.0];
+/// How much of the tint shows on a creature with an effect
+static TINT_STRENGTH : f32 = 0.4;
 static WALL_HEIGHT : f32 = 0.3f32;
+static WATER_HEIGHT : f32 = -0.05f32;
+static DEEPWATER_HEIGHT : f32 = -0.15f32;
+static LAVA_HEIGHT : f32 = -0.05f32;
+static TEXT_COLOR : Color = [1.0f32, 1.0, 1.0, 1.0];
+static HISTORY_BACKGROUND_COLOR : Color = [0.05f32, 0.05, 0.1, 1.0];
+/// Window pixels per font pixel
+static TEXT_SCALE : f32 = 2.0f32;
+/// Font pixels between two lines of text
+static LINE_SPACING : uint = 2;
+/// Window pixels between text and the window edge
+static TEXT_MARGIN : f32 = 8.0f32;
+/// Messages shown during the game
+static LOG_LINES : uint = 5;
+/// Messages scrolled by one key press in the history
+static HISTORY_SCROLL : uint = 10;
+/// Room for text drawn in a frame, six vertices for each run of pixels
+static MAX_TEXT_VERTICES : uint = 262144;
 static HACK_PLAYER_KNOWS_ALL : bool = false;
 static HACK_PLAYER_SEES_EVERYONE : bool = false;
 
@@ -171,6 +235,55 @@ fn grey_out(c : Color) -> Color {
     let [r, g, b, a]  = c;
     [ (r+0.4f32)/4.0f32, (g + 0.4f32)/4.0f32, (b + 0.4f32)/4.0f32, a]
 }
+/// Color and elevation of a tile
+fn tile_look(tiletype : TileType) -> (Color, f32) {
+    match tiletype {
+        Wall => (WALL_COLOR, WALL_HEIGHT),
+        GlassWall => (GLASSWALL_COLOR, WALL_HEIGHT),
+        Floor => (FLOOR_COLOR, 0.0),
+        Sand => (SAND_COLOR, 0.0),
+        Water => (WATER_COLOR, WATER_HEIGHT),
+        DeepWater => (DEEPWATER_COLOR, DEEPWATER_HEIGHT),
+        Lava => (LAVA_COLOR, LAVA_HEIGHT),
+        Debris => (DEBRIS_COLOR, 0.0),
+        Torch => (TORCH_COLOR, WALL_HEIGHT),
+    }
+}
+
+/// Tile at `p` as `player` knows it: what's there when in sight, what's
+/// remembered otherwise
+fn known_tiletype(map : &Map, player : Option<&Creature>, p : Point) -> Option<TileType> {
+    match player {
+        Some(pl) if !pl.sees(p) && !HACK_PLAYER_KNOWS_ALL => pl.memory()
+            .and_then(|memory| memory.tile(p))
+            .map(|tile| tile.tiletype),
+        _ => Some(map.at(p).tiletype),
+    }
+}
+
+fn base_color(is_player : bool, race : Race) -> Color {
+    if is_player {
+        PLAYER_COLOR
+    } else {
+        match race {
+            Scout => SCOUT_COLOR,
+            Grunt => GRUNT_COLOR,
+            Heavy => HEAVY_COLOR,
+            Human => COMPANION_COLOR,
+        }
+    }
+}
+
+fn effect_tint(effect : Effect) -> Color {
+    match effect {
+        Stunned => STUNNED_TINT,
+        Bleeding => BLEEDING_TINT,
+        Slowed => SLOWED_TINT,
+        Blinded => BLINDED_TINT,
+        Enraged => ENRAGED_TINT,
+    }
+}
+
 static BILLION : f32 = 1000000000f32;
 static TAU : f32 = std::f32::consts::PI_2;
 static TILE_OUTER_R : f32 = 1.0f32;
@@ -250,6 +363,24 @@ pub fn point_to_coordinate(p : Point) -> (f32, f32) {
     )
 }
 
+/// Inverse of `point_to_coordinate`
+pub fn coordinate_to_point(x : f32, y : f32) -> Point {
+    let fx = x / (TILE_OUTER_R * 3f32 / 2f32);
+    let fy = (-y / tile_inner_r() - fx) / 2f32;
+    let fz = -fx - fy;
+
+    let (mut rx, mut ry, rz) = (fx.round(), fy.round(), fz.round());
+    let (dx, dy, dz) = ((rx - fx).abs(), (ry - fy).abs(), (rz - fz).abs());
+
+    if dx > dy && dx > dz {
+        rx = -ry - rz;
+    } else if dy > dz {
+        ry = -rx - rz;
+    }
+
+    Point::new(rx as int, ry as int)
+}
+
 impl<C : CommandBuffer, D: gfx::Device<C>> Renderer<C, D> {
     fn new(mut device: D, frame: gfx::Frame) -> Renderer<C, D> {
 
@@ -266,23 +397,53 @@ impl<C : CommandBuffer, D: gfx::Device<C>> Renderer<C, D> {
         let creature_slice = device.create_buffer_static::<u8>(creature_index_data.as_slice())
             .to_slice(gfx::TriangleList);
 
+        let quad_vertex_data = [
+            Vertex { pos: [0.0, 0.0, 0.0], normal: [0.0, 0.0, 1.0] },
+            Vertex { pos: [1.0, 0.0, 0.0], normal: [0.0, 0.0, 1.0] },
+            Vertex { pos: [1.0, 1.0, 0.0], normal: [0.0, 0.0, 1.0] },
+            Vertex { pos: [0.0, 1.0, 0.0], normal: [0.0, 0.0, 1.0] },
+        ];
+        let quad_index_data : [u8, ..6] = [0, 1, 2, 0, 2, 3];
+        let quad_mesh = device.create_mesh(quad_vertex_data.as_slice());
+        let quad_slice = device.create_buffer_static::<u8>(quad_index_data.as_slice())
+            .to_slice(gfx::TriangleList);
+
+        /* Filled anew every frame; unused vertices are left degenerate */
+        let text_buffer = device.create_buffer::<Vertex>(MAX_TEXT_VERTICES, gfx::UsageDynamic);
+        device.update_buffer(text_buffer,
+                             Vec::from_elem(MAX_TEXT_VERTICES, BLANK_VERTEX).as_slice(), 0);
+        let text_mesh = gfx::Mesh::from_format(text_buffer, MAX_TEXT_VERTICES as u32);
+        let text_slice = text_mesh.to_slice(gfx::TriangleList);
+
         let program = device.link_program(VERTEX_SRC.clone(), FRAGMENT_SRC.clone())
             .unwrap();
         let state = gfx::DrawState::new().depth(gfx::state::LessEqual, true).multi_sample();
+        /* Overlay goes over whatever is on the screen already */
+        let overlay_state = gfx::DrawState::new();
 
         let mut graphics = gfx::Graphics::new(device);
         let tile : Batch = graphics.make_batch(&program, &tile_mesh, tile_slice, &state).unwrap();
         let creature : Batch = graphics.make_batch(&program, &creature_mesh, creature_slice, &state).unwrap();
+        let quad : Batch = graphics.make_batch(&program, &quad_mesh, quad_slice, &overlay_state).unwrap();
+        let text : Batch = graphics.make_batch(&program, &text_mesh, text_slice, &overlay_state).unwrap();
 
         let aspect = w as f32 / h as f32;
         let proj = cgmath::perspective(cgmath::deg(45.0f32), aspect, 1.0, 100.0);
+        let overlay_proj = cgmath::ortho(0.0f32, w as f32, h as f32, 0.0, -1.0, 1.0);
 
         Renderer {
             graphics: graphics,
             frame: frame,
             tile_batch : tile,
             creature_batch : creature,
+            quad_batch : quad,
+            font: font::Font::new(),
+            text: Vec::new(),
+            text_buffer: text_buffer,
+            text_batch: text,
+            text_drawn: 0,
             projection: proj,
+            overlay_projection: overlay_proj,
             view: proj,
             cd: gfx::ClearData {
                 color: BACKGROUND_COLOR,
@@ -315,12 +476,46 @@ impl<C : CommandBuffer, D: gfx::Device<C>> Renderer<C, D> {
         self.view = view.mat;
     }
 
+    /// Point on the ground (z = 0) under window coordinates `x`, `y`
+    fn pick(&self, x : f64, y : f64) -> Option<Point> {
+        let nx = 2.0f32 * x as f32 / self.frame.width as f32 - 1.0;
+        let ny = 1.0f32 - 2.0 * y as f32 / self.frame.height as f32;
+
+        let inv = match self.projection.mul_m(&self.view).invert() {
+            Some(inv) => inv,
+            None => return None,
+        };
+
+        let unproject = |z : f32| -> Vector3<f32> {
+            let v = inv.mul_v(&Vector4::new(nx, ny, z, 1.0));
+            Vector3::new(v.x / v.w, v.y / v.w, v.z / v.w)
+        };
+
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+
+        if near.z == far.z {
+            return None;
+        }
+
+        let t = near.z / (near.z - far.z);
+        if t < 0.0 {
+            return None;
+        }
+
+        Some(coordinate_to_point(
+                near.x + (far.x - near.x) * t,
+                near.y + (far.y - near.y) * t,
+                ))
+    }
+
     /// Clear
     fn clear(&mut self) {
         self.graphics.clear(self.cd, gfx::COLOR | gfx::DEPTH, &self.frame);
     }
 
     fn end_frame(&mut self) {
+        self.render_queued_text();
         self.graphics.end_frame();
     }
 
@@ -328,9 +523,9 @@ impl<C : CommandBuffer, D: gfx::Device<C>> Renderer<C, D> {
         self.graphics.draw(batch, params, &self.frame);
     }
 
-    pub fn render_tile(&mut self, p : Point, c : Color, elevate : bool) {
+    pub fn render_tile(&mut self, p : Point, c : Color, elevation : f32) {
         let (px, py) = point_to_coordinate(p);
-        let params = self.render_params(px, py, if elevate {WALL_HEIGHT} else {0.0}, 0.0, c);
+        let params = self.render_params(px, py, elevation, 0.0, c);
         let batch = self.tile_batch;
         self.render_batch(&batch, &params);
     }
@@ -341,8 +536,160 @@ impl<C : CommandBuffer, D: gfx::Device<C>> Renderer<C, D> {
         let batch = self.creature_batch;
         self.render_batch(&batch, &params);
     }
+
+    /// Rectangle over the map, in window pixels
+    pub fn render_rect(&mut self, x : f32, y : f32, w : f32, h : f32, c : Color) {
+        let model = Matrix4::new(
+            w,   0.0, 0.0, 0.0,
+            0.0, h,   0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            x,   y,   0.0, 1.0
+            );
+        let view : Matrix4<f32> = Matrix4::identity();
+
+        let params = Params {
+            projection: self.overlay_projection.into_fixed(),
+            view: view.into_fixed(),
+            color : c,
+            model: model.into_fixed(),
+            light: Vector3::unit_z().into_fixed(),
+        };
+        let batch = self.quad_batch;
+        self.render_batch(&batch, &params);
+    }
+
+    /// Text with top left corner at `x`, `y`, `scale` window pixels
+    /// for every font pixel
+    ///
+    /// Text is only queued, and drawn all at once at the end of the frame.
+    pub fn render_text(&mut self, x : f32, y : f32, scale : f32, text : &str) {
+        for (i, ch) in text.chars().enumerate() {
+            let gx = x + (i * font::GLYPH_ADVANCE) as f32 * scale;
+            for &(row, col, len) in self.font.runs(ch).iter() {
+                let (x0, y0) = (gx + col as f32 * scale, y + row as f32 * scale);
+                let (x1, y1) = (x0 + len as f32 * scale, y0 + scale);
+                for &(vx, vy) in [(x0, y0), (x1, y0), (x1, y1), (x0, y0), (x1, y1), (x0, y1)].iter() {
+                    self.text.push(Vertex { pos: [vx, vy, 0.0], normal: [0.0, 0.0, 1.0] });
+                }
+            }
+        }
+    }
+
+    /// Draw the text queued this frame with one draw call
+    fn render_queued_text(&mut self) {
+        self.text.truncate(MAX_TEXT_VERTICES);
+        let len = self.text.len();
+
+        /* Blank whatever is left from longer text of the last frame */
+        for _ in range(len, self.text_drawn) {
+            self.text.push(BLANK_VERTEX);
+        }
+        if !self.text.is_empty() {
+            self.graphics.device.update_buffer(self.text_buffer, self.text.as_slice(), 0);
+        }
+        self.text.clear();
+        self.text_drawn = len;
+
+        if len == 0 {
+            return;
+        }
+
+        let identity : Matrix4<f32> = Matrix4::identity();
+        let params = Params {
+            projection: self.overlay_projection.into_fixed(),
+            view: identity.into_fixed(),
+            color : TEXT_COLOR,
+            model: identity.into_fixed(),
+            light: Vector3::unit_z().into_fixed(),
+        };
+        let batch = self.text_batch;
+        self.render_batch(&batch, &params);
+    }
+}
+
+
+/// Player's state in the top left corner
+fn render_hud(renderer : &mut Renderer<GlCommandBuffer, GlDevice>,
+              game : &GameState, is_running : bool) {
+    let pl = match game.player {
+        Some(ref pl) => match pl.try_borrow() {
+            Some(pl) => pl,
+            None => return,
+        },
+        None => return,
+    };
+
+    let line_height = (font::GLYPH_HEIGHT + LINE_SPACING) as f32 * TEXT_SCALE;
+
+    let mut lines = vec!(
+        format!("health: {}/{}", pl.health(), pl.max_health()),
+        (if is_running { "running" } else { "walking" }).to_string(),
+        format!("tick: {}", game.current_tick()),
+        match pl.action() {
+            Some(action) => format!("action: {} ({} left)", action, pl.action_delay()),
+            None => format!("action: none ({} left)", pl.action_delay()),
+        },
+        format!("enemies in sight: {}", game.visible_enemies(&*pl)),
+        );
+
+    match pl.progress() {
+        Some(progress) => {
+            lines.push(format!("level: {} xp: {}/{}",
+                               progress.level, progress.xp, progress.xp_to_level()));
+            let taken : Vec<&str> = progress.perks.iter().map(|perk| perk.name()).collect();
+            if !taken.is_empty() {
+                lines.push(format!("perks: {}", taken.connect(", ")));
+            }
+            if progress.perks_pending > 0 {
+                lines.push(format!("perks to pick: {}", progress.perks_pending));
+                for (i, perk) in ALL_PERKS.iter().enumerate() {
+                    if !progress.has(*perk) {
+                        lines.push(format!("  {}: {}", i + 1, perk.name()));
+                    }
+                }
+            }
+        },
+        None => {}
+    }
+
+    for (i, line) in lines.iter().enumerate() {
+        renderer.render_text(
+            TEXT_MARGIN, TEXT_MARGIN + i as f32 * line_height,
+            TEXT_SCALE, line.as_slice()
+            );
+    }
 }
 
+/// Latest messages in the bottom left corner, or the whole history
+/// over the map
+///
+/// The history shows as many messages as fit in the window, `scroll`
+/// messages back from the latest. `scroll` is limited so that the
+/// window stays full.
+fn render_log(renderer : &mut Renderer<GlCommandBuffer, GlDevice>,
+              log : &MessageLog, history : bool, scroll : &mut uint) {
+    let (w, h) = (renderer.frame.width as f32, renderer.frame.height as f32);
+    let line_height = (font::GLYPH_HEIGHT + LINE_SPACING) as f32 * TEXT_SCALE;
+
+    let lines = if history {
+        renderer.render_rect(0.0, 0.0, w, h, HISTORY_BACKGROUND_COLOR);
+
+        let fit = ((h - 2.0 * TEXT_MARGIN) / line_height).max(1.0) as uint;
+        let max_scroll = if log.len() > fit { log.len() - fit } else { 0 };
+        *scroll = cmp::min(*scroll, max_scroll);
+        log.page(fit, *scroll)
+    } else {
+        log.recent(LOG_LINES)
+    };
+
+    let top = h - TEXT_MARGIN - line_height * lines.len() as f32;
+    for (i, line) in lines.iter().enumerate() {
+        renderer.render_text(
+            TEXT_MARGIN, top + i as f32 * line_height,
+            TEXT_SCALE, line.as_slice()
+            );
+    }
+}
 
 /// linearly interpolate between two values
 fn mix<F : FloatMath> (x : F, y : F, a : F) -> F {
@@ -387,20 +734,47 @@ pub struct PistonUI {
     renderer : Renderer<GlCommandBuffer, GlDevice>,
     render_controller : RenderController,
     input_controller: InputController,
+    /// Actor controlling the player when the other one is not
+    idle_actor: Option<Box<Actor+'static>>,
+    log: MessageLog,
+    /// Whole message history shown instead of the map
+    show_history: bool,
+    /// Messages between the latest one and the bottom of the history
+    history_scroll: uint,
 }
 
 pub struct RenderController {
     player_pos: Position,
+    /// Tile under the mouse cursor
+    hovered: Option<Point>,
     camera_pos : SmoothMovement<Point3<f32>>,
     camera_focus : SmoothMovement<Point3<f32>>,
 }
 
+/// Moving on its own, one step at a time
+pub enum Travel {
+    /// Running, if the player asked for it; walking is quieter
+    Explore(bool),
+    TravelTo(Point),
+}
+
 pub struct InputController {
     shift_pressed: bool,
     alt_pressed: bool,
     ctrl_pressed: bool,
     is_running: bool,
-    action_queue: RingBuf<Action>,
+    travel: Option<Travel>,
+    /// `was_attacked_ns` of the player when travel started
+    travel_hit_ns: Option<u64>,
+    /// Mouse cursor position in the window
+    cursor: Option<(f64, f64)>,
+    /// Tile under the mouse cursor
+    hovered: Option<Point>,
+    /// Order for companions, not given yet
+    order: Option<Order>,
+    /// Perk chosen for a level up, not taken yet
+    perk: Option<Perk>,
+    action_queue: ActionQueue,
 }
 
 impl InputController {
@@ -410,7 +784,13 @@ impl InputController {
             alt_pressed: false,
             ctrl_pressed: false,
             is_running: true,
-            action_queue: RingBuf::new(),
+            travel: None,
+            travel_hit_ns: None,
+            cursor: None,
+            hovered: None,
+            order: None,
+            perk: None,
+            action_queue: Rc::new(RefCell::new(RingBuf::new())),
         }
     }
 
@@ -423,20 +803,130 @@ impl InputController {
     }
 
     fn push_move_or_run(&mut self, dir : Direction) {
+        self.stop_travel();
         let a = self.move_or_run(dir);
-        self.action_queue.push_back(a)
+        self.action_queue.borrow_mut().push_back(a)
     }
 
     fn push_turn(&mut self, dir : Direction) {
-        self.action_queue.push_back(Turn(dir))
+        self.stop_travel();
+        self.action_queue.borrow_mut().push_back(Turn(dir))
     }
 
     fn push_melee(&mut self, dir : Direction) {
-        self.action_queue.push_back(Melee(dir))
+        self.stop_travel();
+        self.action_queue.borrow_mut().push_back(Melee(dir))
     }
 
     fn push_wait(&mut self) {
-        self.action_queue.push_back(Wait)
+        self.stop_travel();
+        self.action_queue.borrow_mut().push_back(Wait)
+    }
+
+    fn toggle_exploring(&mut self, run : bool) {
+        match self.travel {
+            Some(Explore(_)) => self.stop_travel(),
+            _ => self.start_travel(Explore(run)),
+        }
+    }
+
+    /// Travel to `p`, eg. after the player clicked on it
+    pub fn travel_to(&mut self, p : Point) {
+        self.action_queue.borrow_mut().clear();
+        self.start_travel(TravelTo(p));
+    }
+
+    fn start_travel(&mut self, travel : Travel) {
+        self.travel = Some(travel);
+        self.travel_hit_ns = None;
+    }
+
+    fn stop_travel(&mut self) {
+        self.travel = None;
+    }
+
+    /// Queue next step of travel or auto-explore
+    ///
+    /// Auto-explore walks towards the nearest known tile next to an
+    /// unknown one. Paths go only through what the player knows. Both stop
+    /// when the destination is reached, an enemy comes into view, someone
+    /// blocks the way or the player gets attacked.
+    ///
+    /// Returns a message for the player when no way was found.
+    fn travel(&mut self, game : &GameState) -> Option<&'static str> {
+        if self.travel.is_none() || self.has_actions() {
+            return None;
+        }
+
+        let pl = match game.player {
+            Some(ref pl) => pl.borrow(),
+            None => {
+                self.stop_travel();
+                return None;
+            }
+        };
+
+        if self.travel_hit_ns.is_none() {
+            self.travel_hit_ns = Some(pl.was_attacked_ns());
+        }
+
+        if !pl.is_alive() || pl.was_attacked_ns() != self.travel_hit_ns.unwrap()
+            || game.visible_enemies(&*pl) > 0 {
+            self.stop_travel();
+            return None;
+        }
+
+        let map = &*game.map;
+        let knowledge = match pl.memory() {
+            Some(memory) => path::Remembered(memory, pl.visible()),
+            None => path::Everything,
+        };
+        let travel = self.travel.unwrap();
+        match travel {
+            TravelTo(p) if map.wrap(p) == pl.pos().p => {
+                self.stop_travel();
+                return None;
+            },
+            _ => {}
+        }
+
+        let path = match travel {
+            Explore(_) => path::path_to_nearest(map, &knowledge, *pl.pos(), pl.race(), |p| {
+                pl.knows(p) && p.neighbors().iter().any(|&n| !pl.knows(map.wrap(n)))
+            }),
+            TravelTo(p) => path::path_to(map, &knowledge, *pl.pos(), p, pl.race()),
+        };
+
+        let path = match path {
+            Ok(path) => path,
+            Err(why) => {
+                self.stop_travel();
+                return Some(match (travel, why) {
+                    (_, path::TooFar) => "It's too far to find the way",
+                    (Explore(_), path::Unreachable) => "You explored everything you can reach",
+                    (TravelTo(_), path::Unreachable) => "You know no way there",
+                });
+            }
+        };
+
+        match path.as_slice().head() {
+            /* Someone's in the way, eg. standing on the destination */
+            Some(&Move(dir)) if {
+                let next = map.wrap(pl.pos().p + (pl.pos().dir + dir));
+                pl.sees(next) && map.at(next).creature.is_some()
+            } => self.stop_travel(),
+            Some(&Move(dir)) => {
+                let a = match travel {
+                    Explore(false) => Move(dir),
+                    _ => self.move_or_run(dir),
+                };
+                self.action_queue.borrow_mut().push_back(a);
+            },
+            Some(&action) => self.action_queue.borrow_mut().push_back(action),
+            None => self.stop_travel(),
+        }
+
+        None
     }
 
     pub fn push_input(&mut self, i : InputEvent) {
@@ -460,9 +950,19 @@ impl InputController {
                     (key::L, _, true) => self.push_melee(Right),
                     (key::H, _, true) => self.push_melee(Left),
                     (key::Period, _, _) => self.push_wait(),
+                    (key::X, shift, _) => self.toggle_exploring(shift),
+                    (key::F, _, _) => self.order = Some(Follow),
+                    (key::G, _, _) => self.order = Some(Hold),
+                    (key::T, _, _) => self.order = self.hovered.map(|p| AttackAt(p)),
+                    (key::D1, _, _) => self.perk = Some(ALL_PERKS[0]),
+                    (key::D2, _, _) => self.perk = Some(ALL_PERKS[1]),
+                    (key::D3, _, _) => self.perk = Some(ALL_PERKS[2]),
                     _ => { }
                 }
             },
+            input::Move(input::MouseCursor(x, y)) => {
+                self.cursor = Some((x, y));
+            },
             Release(Keyboard(k)) => {
                 match k {
                     key::LShift|key::RShift => {
@@ -481,8 +981,33 @@ impl InputController {
         }
     }
 
-    pub fn pop_action(&mut self) -> Option<Action> {
-        self.action_queue.pop_front()
+    pub fn cursor(&self) -> Option<(f64, f64)> {
+        self.cursor
+    }
+
+    /// Actor performing the actions from the keyboard
+    pub fn actor(&self) -> InputActor {
+        InputActor::new(self.action_queue.clone())
+    }
+
+    /// Order for companions given since the last call
+    pub fn take_order(&mut self) -> Option<Order> {
+        self.order.take()
+    }
+
+    /// Perk chosen since the last call
+    pub fn take_perk(&mut self) -> Option<Perk> {
+        self.perk.take()
+    }
+
+    fn has_actions(&self) -> bool {
+        !self.action_queue.borrow().is_empty()
+    }
+
+    /// Drop everything queued
+    fn clear(&mut self) {
+        self.stop_travel();
+        self.action_queue.borrow_mut().clear();
     }
 }
 
@@ -492,6 +1017,7 @@ impl RenderController {
         let cf = SmoothMovement::new(2.0f32);
         RenderController {
             player_pos: Position::new(Point::new(0,0), North),
+            hovered: None,
             camera_pos: cp,
             camera_focus: cf,
         }
@@ -509,15 +1035,12 @@ impl RenderController {
         let player = player.as_ref().and_then(|pl| pl.try_borrow());
 
         game.map.for_each_point(|ap| {
+            /* Out of sight, draw what the player remembers, not what's there */
+            let tiletype = known_tiletype(&*game.map, player.as_ref().map(|pl| &**pl), ap);
 
-            if player.as_ref().map_or(true, |pl| pl.knows(ap) || HACK_PLAYER_KNOWS_ALL) {
-                let tiletype = game.map.at(ap).tiletype;
-                let (color, elevate) = match tiletype {
-                    Wall => (WALL_COLOR, true),
-                    GlassWall => (GLASSWALL_COLOR, true),
-                    Floor => (FLOOR_COLOR, false),
-                    Sand => (SAND_COLOR, false),
-                };
+            if tiletype.is_some() {
+                let tiletype = tiletype.unwrap();
+                let (color, elevation) = tile_look(tiletype);
 
                 let color = if player.as_ref().map_or(
                     false, |pl| !pl.sees(ap) && pl.is_alive()
@@ -527,10 +1050,24 @@ impl RenderController {
                     color
                 };
 
-                renderer.render_tile(ap, color, elevate);
+                renderer.render_tile(ap, color, elevation);
             };
         });
 
+        match self.hovered {
+            Some(p) => {
+                let p = game.map.wrap(p);
+                /* Unknown tiles are shown flat, not giving away what's there */
+                let elevation = known_tiletype(&*game.map, player.as_ref().map(|pl| &**pl), p)
+                    .map_or(0.0, |tiletype| {
+                        let (_, elevation) = tile_look(tiletype);
+                        elevation
+                    });
+                renderer.render_tile(p, HOVER_COLOR, elevation + HOVER_HEIGHT);
+            },
+            None => {}
+        }
+
         for creature in game.creatures_iter() {
             let creature = creature.borrow();
 
@@ -548,23 +1085,42 @@ impl RenderController {
                 None => {}
             }
         };
+
+        /* Creatures out of sight stay where the player last saw them,
+         * whatever happened to them since */
+        match player {
+            Some(ref pl) if pl.is_alive() && !HACK_PLAYER_SEES_EVERYONE => {
+                match pl.memory() {
+                    Some(memory) => for ghost in memory.creatures() {
+                        if pl.sees(ghost.pos.p) {
+                            continue;
+                        }
+
+                        let color = grey_out(base_color(ghost.is_player, ghost.race));
+                        renderer.render_creature(ghost.pos, color);
+                    },
+                    None => {}
+                }
+            },
+            _ => {}
+        }
     }
 
     fn creature_color(&self, cr : &Creature) -> Option<Color> {
         let now_ns = time::precise_time_ns();
         let duration_s = 0.8f32;
 
-        let base_color = if cr.is_player() {
-            PLAYER_COLOR
-        } else {
-            match cr.race() {
-                Scout => SCOUT_COLOR,
-                Grunt => GRUNT_COLOR,
-                Heavy => HEAVY_COLOR,
-                Human => panic!(),
-            }
-        };
-        let color = base_color;
+        let color = base_color(cr.is_player(), cr.race());
+
+        let color = cr.effects().iter().fold(color, |color, &(effect, _)| {
+            let tint = effect_tint(effect);
+            [
+                mix(color[0], tint[0], TINT_STRENGTH),
+                mix(color[1], tint[1], TINT_STRENGTH),
+                mix(color[2], tint[2], TINT_STRENGTH),
+                color[3],
+            ]
+        });
 
         let since_s = (now_ns - cr.was_attacked_ns()) as f32 / BILLION;
         let color = if since_s < duration_s {
@@ -665,33 +1221,76 @@ impl PistonUI {
         (PistonUI {
             render_controller: RenderController::new(),
             input_controller: InputController::new(),
+            idle_actor: None,
+            log: MessageLog::new(),
+            show_history: false,
+            history_scroll: 0,
             renderer: renderer,
         }, window)
     }
 
     fn game_update(&mut self, game : &mut GameState) {
-        loop {
-            if game.tick() {
-                match self.input_controller.pop_action() {
-                    Some(action) => {
-                        game.player.as_ref().map(|pl| pl.borrow_mut().action_set(action));
-                    },
-                    _ => {
-                        break;
-                    }
-                };
+        match self.input_controller.take_order() {
+            Some(order) => game.order_companions(order),
+            None => {}
+        }
 
+        match self.input_controller.take_perk() {
+            Some(perk) => { game.choose_perk(perk); },
+            None => {}
+        }
+
+        loop {
+            let waiting = game.tick();
+            game.check_spotted();
+            if waiting {
+                match self.input_controller.travel(game) {
+                    Some(msg) => self.log.push(msg.to_string()),
+                    None => {}
+                }
+                if !self.input_controller.has_actions() {
+                    break;
+                }
             } else {
                 break;
             }
         }
+
+        for event in game.take_events().iter() {
+            match log::describe(event) {
+                Some(msg) => self.log.push(msg),
+                None => {}
+            }
+        }
+
         match game.player {
             Some(ref pl) => self.render_controller.set_player_pos(&*pl.borrow()),
             None => {}
         }
     }
 
+    /// Switch the player between keyboard and AI control
+    fn toggle_autopilot(&mut self, game : &GameState) {
+        match (game.player.as_ref(), self.idle_actor.take()) {
+            (Some(pl), Some(actor)) => {
+                self.idle_actor = Some(pl.borrow_mut().set_actor(actor));
+            },
+            (_, actor) => self.idle_actor = actor,
+        }
+        self.input_controller.clear();
+    }
+
     pub fn run (&mut self, window : Window, game : &mut GameState) {
+        game.spawn_companion();
+
+        match game.player {
+            Some(ref pl) => {
+                let actor = box self.input_controller.actor();
+                self.idle_actor = Some(pl.borrow_mut().set_actor(actor));
+            },
+            None => {}
+        }
+
         game.update_player_los();
         {
             let ref pl = game.player.as_ref();
@@ -720,6 +1319,10 @@ impl PistonUI {
                     let &PistonUI {
                         ref mut renderer,
                         ref mut render_controller,
+                        ref mut input_controller,
+                        ref log,
+                        show_history,
+                        ref mut history_scroll,
                         ..
                     } = self;
 
@@ -728,14 +1331,41 @@ impl PistonUI {
                     render_time = t;
                     render_controller.update_movement(dt as f32 / BILLION as f32);
                     render_controller.update_camera(renderer);
+                    render_controller.hovered = input_controller.cursor()
+                        .and_then(|(x, y)| renderer.pick(x, y));
+                    input_controller.hovered = render_controller.hovered;
                     renderer.clear();
                     render_controller.render_map(renderer, game);
+                    if !show_history {
+                        render_hud(renderer, game, input_controller.is_running);
+                    }
+                    render_log(renderer, log, show_history, history_scroll);
                     renderer.end_frame();
                 },
                 Update(_) => {
                     self.game_update(game);
                 },
                 Input(i) => {
+                    match i {
+                        Press(Mouse(mouse::Left)) => {
+                            match self.render_controller.hovered {
+                                Some(p) => self.input_controller.travel_to(p),
+                                None => {}
+                            }
+                        },
+                        Press(Keyboard(key::Tab)) => self.toggle_autopilot(game),
+                        Press(Keyboard(key::M)) => {
+                            self.show_history = !self.show_history;
+                            self.history_scroll = 0;
+                        },
+                        Press(Keyboard(key::PageUp)) if self.show_history => {
+                            self.history_scroll += HISTORY_SCROLL;
+                        },
+                        Press(Keyboard(key::PageDown)) if self.show_history => {
+                            self.history_scroll -= cmp::min(self.history_scroll, HISTORY_SCROLL);
+                        },
+                        _ => {}
+                    }
                     self.input_controller.push_input(i.clone());
                 }
             }